@@ -0,0 +1,208 @@
+"""Python processing backend, run by `src-tauri/src/image/python.rs`.
+
+The app writes one JSON request on stdin:
+
+    {"protocol": 1, "action": "import" | "filter", "paths": [...],
+     "cache_dir": "...", "filter": "blur" | null, "params": {...}}
+
+and reads JSON lines back from stdout, each one of
+
+    {"event": "progress", "data": {"percentage", "step", "file": {"index", "filename", "percentage"}}}
+    {"event": "error", "data": {"path", "message"}}
+    {"event": "result", "data": {...}}
+
+Progress is shown as is, errors and results become one entry per file. Anything else
+printed to stdout is logged by the app, so plain `print` calls are safe for debugging.
+"""
+
+import hashlib
+import json
+import os
+import sys
+
+PROTOCOL_VERSION = 1
+DEFAULT_PREVIEW_SIZE = 1024
+
+# Cached copies are named after this prefix and a hash of the pixels, so they never collide
+# with the ones the Rust pipeline writes for the same file
+HASH_PREFIX = "py-"
+
+
+def send(event, data):
+    sys.stdout.write(json.dumps({"event": event, "data": data}) + "\n")
+    sys.stdout.flush()
+
+
+def send_progress(current_step, total_steps, step, index, path, file_step, steps_per_file):
+    send("progress", {
+        "percentage": min(100.0, current_step / total_steps * 100.0),
+        "step": step,
+        "file": {
+            "index": index,
+            "filename": os.path.basename(path),
+            "percentage": min(100.0, file_step / steps_per_file * 100.0),
+        },
+    })
+
+
+def load_image(path):
+    from PIL import Image, ImageOps
+
+    image = Image.open(path)
+    image.load()
+
+    # Stored upright, like the Rust pipeline does
+    return ImageOps.exif_transpose(image)
+
+
+def image_hash(image):
+    hasher = hashlib.sha256()
+    hasher.update("{}:{}x{}".format(image.mode, image.width, image.height).encode())
+    hasher.update(image.tobytes())
+    return HASH_PREFIX + hasher.hexdigest()
+
+
+def preview_size(width, height, maximum):
+    longest_edge = max(width, height)
+    if longest_edge <= maximum:
+        return width, height
+
+    scale = longest_edge / maximum
+    return max(1, round(width / scale)), max(1, round(height / scale))
+
+
+def import_image(path, cache_dir, params):
+    from PIL import Image
+
+    image = load_image(path)
+    hash = image_hash(image)
+
+    highres_path = os.path.join(cache_dir, "highres", hash + ".tiff")
+    lowres_path = os.path.join(cache_dir, "lowres", hash + ".png")
+
+    if not os.path.exists(highres_path):
+        image.save(highres_path, format="TIFF", compression="tiff_deflate")
+
+    maximum = int(params.get("preview_size", DEFAULT_PREVIEW_SIZE))
+    lowres_width, lowres_height = preview_size(image.width, image.height, maximum)
+    if not os.path.exists(lowres_path):
+        preview = image if image.mode in ("L", "LA", "RGB", "RGBA") else image.convert("RGBA")
+        preview.resize((lowres_width, lowres_height), Image.Resampling.LANCZOS).save(lowres_path, format="PNG")
+
+    paths = {"highres": highres_path, "lowres": lowres_path, "tiles": None}
+    dimensions = {
+        "highres": {"width": image.width, "height": image.height},
+        "lowres": {"width": lowres_width, "height": lowres_height},
+    }
+
+    return {
+        "hash": hash,
+        "backend": "python",
+        "source_path": path,
+        "paths": paths,
+        "dimensions": dimensions,
+        "pages": [{"index": 0, "delay_ms": None, "paths": paths, "dimensions": dimensions}],
+        "animation": None,
+    }
+
+
+def apply_filter(image, name, params):
+    from PIL import ImageFilter, ImageOps
+
+    if name == "grayscale":
+        return ImageOps.grayscale(image)
+    if name == "blur":
+        return image.filter(ImageFilter.GaussianBlur(float(params.get("radius", 2.0))))
+    if name == "sharpen":
+        return image.filter(ImageFilter.UnsharpMask(
+            radius=float(params.get("radius", 2.0)),
+            percent=int(params.get("percent", 150)),
+            threshold=int(params.get("threshold", 3)),
+        ))
+    if name == "edges":
+        return image.convert("RGB").filter(ImageFilter.FIND_EDGES)
+    if name == "autocontrast":
+        return ImageOps.autocontrast(image.convert("RGB"), cutoff=float(params.get("cutoff", 0)))
+
+    raise ValueError("Unknown filter: {}".format(name))
+
+
+def filter_image(path, name, cache_dir, params):
+    image = load_image(path)
+    hash = image_hash(image)
+
+    # Named after the source's hash so the copy goes when the cache evicts that image
+    output_path = os.path.join(cache_dir, "lowres", "{}.{}.png".format(hash, name))
+    filtered = apply_filter(image, name, params)
+    filtered.save(output_path, format="PNG")
+
+    return {
+        "hash": hash,
+        "backend": "python",
+        "source_path": path,
+        "filter": name,
+        "params": params,
+        "path": output_path,
+        "dimensions": {"width": filtered.width, "height": filtered.height},
+    }
+
+
+def run(request):
+    action = request.get("action")
+    paths = request.get("paths", [])
+    cache_dir = request.get("cache_dir", "")
+    params = request.get("params") or {}
+
+    if request.get("protocol") != PROTOCOL_VERSION:
+        message = "Unsupported protocol version {}, expected {}".format(request.get("protocol"), PROTOCOL_VERSION)
+        for path in paths:
+            send("error", {"path": path, "message": message})
+        return
+
+    for cache_subdir in ("highres", "lowres"):
+        os.makedirs(os.path.join(cache_dir, cache_subdir), exist_ok=True)
+
+    steps_per_file = 2
+    total_steps = max(1, len(paths) * steps_per_file)
+    current_step = 0
+
+    for index, path in enumerate(paths):
+        current_step += 1
+        send_progress(current_step, total_steps, "Loading image", index, path, 1, steps_per_file)
+
+        step = "Saved image"
+        try:
+            if action == "import":
+                result = import_image(path, cache_dir, params)
+            elif action == "filter":
+                result = filter_image(path, request.get("filter"), cache_dir, params)
+            else:
+                raise ValueError("Unknown action: {}".format(action))
+        except ImportError as e:
+            send("error", {"path": path, "message": "Missing Python dependency: {}".format(e.name)})
+            step = "Skipped failed image"
+        except Exception as e:
+            send("error", {"path": path, "message": str(e)})
+            step = "Skipped failed image"
+        else:
+            send("result", result)
+
+        current_step += 1
+        send_progress(current_step, total_steps, step, index, path, steps_per_file, steps_per_file)
+
+
+def main():
+    line = sys.stdin.readline()
+    if not line:
+        sys.exit("No request received on stdin")
+
+    try:
+        request = json.loads(line)
+    except json.JSONDecodeError as e:
+        sys.exit("Malformed request: {}".format(e))
+
+    run(request)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,395 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tauri::State;
+
+use crate::image::error::{ImportError, ImportFailure};
+use crate::image::formats::{self, OutputFormat};
+use crate::image::jobs::{CancellationToken, JobRegistry};
+use crate::image::lowres_rs::{self, ImportOptions};
+use crate::image::metadata;
+use crate::image::pool::MemoryBudget;
+use crate::image::resize::{self, ResizeBackend};
+use crate::image::resolution;
+use crate::image::tiff_writer;
+
+/// Heap tracking behind the per-stage memory peaks. Installing it replaces the allocator of
+/// the whole app and makes every allocation pay for two atomics, so it is only built with
+/// the `benchmark` feature. The counts are process wide, which is why a benchmark refuses
+/// to start while other jobs are running.
+#[cfg(feature = "benchmark")]
+mod heap {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct TrackingAllocator;
+
+    static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+    static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+    fn record_allocation(size: usize) {
+        let allocated = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK.fetch_max(allocated, Ordering::Relaxed);
+    }
+
+    unsafe impl GlobalAlloc for TrackingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc(layout);
+            if !ptr.is_null() {
+                record_allocation(layout.size());
+            }
+            ptr
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc_zeroed(layout);
+            if !ptr.is_null() {
+                record_allocation(layout.size());
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout);
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new_ptr = System.realloc(ptr, layout, new_size);
+            if !new_ptr.is_null() {
+                ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+                record_allocation(new_size);
+            }
+            new_ptr
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: TrackingAllocator = TrackingAllocator;
+
+    /// Starts a measurement, returning the bytes live right now.
+    pub fn start() -> usize {
+        let baseline = ALLOCATED.load(Ordering::Relaxed);
+        PEAK.store(baseline, Ordering::Relaxed);
+        baseline
+    }
+
+    /// Most bytes allocated on top of `baseline` since [`start`] returned it.
+    pub fn peak_since(baseline: usize) -> u64 {
+        PEAK.load(Ordering::Relaxed).saturating_sub(baseline) as u64
+    }
+}
+
+/// The parts of the import pipeline that are timed separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Decode,
+    Hash,
+    Resize,
+    EncodeTiff,
+    EncodePng,
+    Metadata,
+}
+
+impl Stage {
+    const ALL: [Stage; 6] = [Stage::Decode, Stage::Hash, Stage::Resize, Stage::EncodeTiff, Stage::EncodePng, Stage::Metadata];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Stage::Decode => "decode",
+            Stage::Hash => "hash",
+            Stage::Resize => "resize",
+            Stage::EncodeTiff => "encode_tiff",
+            Stage::EncodePng => "encode_png",
+            Stage::Metadata => "metadata",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct StageSamples {
+    durations: Vec<Duration>,
+    peak_memory: Option<u64>,
+}
+
+fn empty_samples() -> Vec<StageSamples> {
+    Stage::ALL.iter().map(|_| StageSamples::default()).collect()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageReport {
+    pub stage: Stage,
+    pub samples: usize,
+    pub min_ms: f64,
+    pub median_ms: f64,
+    pub p95_ms: f64,
+    /// Most heap memory the stage allocated on top of what was live when it started,
+    /// only measured in builds with the `benchmark` feature
+    pub peak_memory_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackendReport {
+    pub backend: ResizeBackend,
+    pub stages: Vec<StageReport>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkReport {
+    pub iterations: u32,
+    /// Whether heap memory was measured, only builds with the `benchmark` feature track it.
+    /// Without it every `peak_memory_bytes` is null.
+    pub memory_tracked: bool,
+    pub files: Vec<String>,
+    pub backends: Vec<BackendReport>,
+    /// Files that failed in any run, each listed once
+    pub failures: Vec<ImportFailure>,
+    pub export_path: Option<String>,
+}
+
+/// Format `export_path` is written in, picked from its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExportFormat {
+    Json,
+    Csv,
+}
+
+// Nearest-rank percentile of sorted samples
+fn percentile_ms(sorted: &[Duration], percentile: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+
+    let rank = ((percentile * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len());
+    sorted[rank - 1].as_secs_f64() * 1000.0
+}
+
+impl StageSamples {
+    fn merge(&mut self, other: StageSamples) {
+        self.durations.extend(other.durations);
+        self.peak_memory = match (self.peak_memory, other.peak_memory) {
+            (Some(peak), Some(other)) => Some(peak.max(other)),
+            (peak, other) => peak.or(other),
+        };
+    }
+
+    fn report(mut self, stage: Stage) -> StageReport {
+        self.durations.sort_unstable();
+
+        StageReport {
+            stage,
+            samples: self.durations.len(),
+            min_ms: percentile_ms(&self.durations, 0.0),
+            median_ms: percentile_ms(&self.durations, 0.5),
+            p95_ms: percentile_ms(&self.durations, 0.95),
+            peak_memory_bytes: self.peak_memory,
+        }
+    }
+}
+
+fn measure<T>(
+    samples: &mut [StageSamples],
+    stage: Stage,
+    run: impl FnOnce() -> Result<T, ImportError>,
+) -> Result<T, ImportError> {
+    #[cfg(feature = "benchmark")]
+    let baseline = heap::start();
+
+    let start = Instant::now();
+    let result = run()?;
+    let elapsed = start.elapsed();
+
+    let samples = &mut samples[stage as usize];
+    samples.durations.push(elapsed);
+
+    #[cfg(feature = "benchmark")]
+    {
+        let peak = heap::peak_since(baseline);
+        samples.peak_memory = Some(samples.peak_memory.map_or(peak, |previous| previous.max(peak)));
+    }
+
+    Ok(result)
+}
+
+/// Runs one file through the same steps an import does, writing its copies into `scratch_dir`.
+fn run_file(
+    file: &str,
+    options: &ImportOptions,
+    budget: &MemoryBudget,
+    cancel: &CancellationToken,
+    scratch_dir: &Path,
+    samples: &mut [StageSamples],
+) -> Result<(), ImportError> {
+    let (image, resolution, icc_profile) = measure(samples, Stage::Decode, || {
+        let mut decoded = lowres_rs::decode_source(file, options, budget, cancel)?;
+        decoded.image.apply_orientation(decoded.orientation);
+        Ok((decoded.image, decoded.resolution.oriented(decoded.orientation), decoded.icc_profile))
+    })?;
+
+    measure(samples, Stage::Hash, || Ok(lowres_rs::get_image_hash(&image)))?;
+
+    // Includes the 8-bit sRGB copy the pipeline makes before scaling
+    let preview = measure(samples, Stage::Resize, || {
+        let (width, height) = (image.width(), image.height());
+        let (lowres_width, lowres_height) = lowres_rs::calculate_new_dimensions(width, height, options.preview_size)
+            .unwrap_or((width, height));
+
+        let (preview, _) = lowres_rs::to_srgb_preview(&image, icc_profile.as_ref());
+        resize::resize(&preview, lowres_width, lowres_height, options.resample_filter, options.resize_backend)
+            .map_err(ImportError::Resize)
+    })?;
+
+    measure(samples, Stage::EncodeTiff, || {
+        tiff_writer::write_tiff(&image, &scratch_dir.join("highres.tiff"), Some(&resolution), icc_profile.as_ref())
+            .map_err(|e| ImportError::Save(std::io::Error::other(e)))
+    })?;
+
+    measure(samples, Stage::EncodePng, || {
+        formats::write_preview(&preview, OutputFormat::Png, options.preview_quality, &scratch_dir.join("lowres.png"))
+            .map_err(ImportError::Save)
+    })?;
+
+    measure(samples, Stage::Metadata, || {
+        metadata::read_metadata(Path::new(file));
+        resolution::read_resolution(Path::new(file));
+        lowres_rs::get_orientation(file);
+        Ok(())
+    })
+}
+
+fn to_csv(report: &BenchmarkReport) -> String {
+    let mut csv = String::from("backend,stage,samples,min_ms,median_ms,p95_ms,peak_memory_bytes\n");
+
+    for backend in &report.backends {
+        for stage in &backend.stages {
+            csv.push_str(&format!(
+                "{},{},{},{:.3},{:.3},{:.3},{}\n",
+                backend.backend.name(),
+                stage.stage.name(),
+                stage.samples,
+                stage.min_ms,
+                stage.median_ms,
+                stage.p95_ms,
+                stage.peak_memory_bytes.map(|bytes| bytes.to_string()).unwrap_or_default()
+            ));
+        }
+    }
+
+    csv
+}
+
+fn export_report(report: &BenchmarkReport, export_path: &Path) -> Result<(), String> {
+    let format = match export_path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) if extension.eq_ignore_ascii_case("csv") => ExportFormat::Csv,
+        _ => ExportFormat::Json,
+    };
+
+    let contents = match format {
+        ExportFormat::Csv => to_csv(report),
+        ExportFormat::Json => serde_json::to_string_pretty(report)
+            .map_err(|e| format!("Failed to serialize benchmark report: {}", e))?,
+    };
+
+    std::fs::write(export_path, contents).map_err(|e| format!("Failed to write benchmark report: {}", e))
+}
+
+/// Times every stage of the pipeline for each file, `iterations` times per backend.
+/// Files run one after another on a single thread so the timings do not compete.
+pub fn run_benchmark(
+    paths: &[String],
+    backends: &[ResizeBackend],
+    iterations: u32,
+    scratch_dir: &Path,
+    cancel: &CancellationToken,
+) -> Result<BenchmarkReport, String> {
+    std::fs::create_dir_all(scratch_dir).map_err(|e| format!("Failed to create benchmark directory: {}", e))?;
+
+    let iterations = iterations.max(1);
+    let mut failures: Vec<ImportFailure> = Vec::new();
+    let mut backend_reports = Vec::with_capacity(backends.len());
+
+    for backend in backends {
+        let options = ImportOptions { resize_backend: *backend, ..Default::default() };
+        let budget = MemoryBudget::new(options.memory_budget_bytes());
+        let mut samples = empty_samples();
+
+        for _ in 0..iterations {
+            for file in paths {
+                if cancel.is_cancelled() {
+                    return Err("Benchmark was cancelled".to_string());
+                }
+
+                // A file that fails part way counts for none of the stages, not only the ones it reached
+                let mut file_samples = empty_samples();
+                match run_file(file, &options, &budget, cancel, scratch_dir, &mut file_samples) {
+                    Ok(()) => {
+                        for (total, file_samples) in samples.iter_mut().zip(file_samples) {
+                            total.merge(file_samples);
+                        }
+                    }
+                    Err(e) => {
+                        if !failures.iter().any(|failure| &failure.path == file) {
+                            failures.push(ImportFailure::new(file, &e));
+                        }
+                    }
+                }
+            }
+        }
+
+        backend_reports.push(BackendReport {
+            backend: *backend,
+            stages: Stage::ALL.into_iter().zip(samples).map(|(stage, samples)| samples.report(stage)).collect(),
+        });
+    }
+
+    Ok(BenchmarkReport {
+        iterations,
+        memory_tracked: cfg!(feature = "benchmark"),
+        files: paths.to_vec(),
+        backends: backend_reports,
+        failures,
+        export_path: None,
+    })
+}
+
+#[tauri::command]
+pub async fn benchmark_pipeline(
+    paths: Vec<String>,
+    backends: Vec<ResizeBackend>,
+    iterations: u32,
+    export_path: Option<String>,
+    registry: State<'_, JobRegistry>,
+) -> Result<BenchmarkReport, String> {
+    let backends = if backends.is_empty() {
+        vec![ResizeBackend::Fimg, ResizeBackend::FastImageResize]
+    } else {
+        backends
+    };
+
+    // Other jobs would compete for the CPU and show up in the process wide memory counts
+    if !registry.running().is_empty() {
+        return Err("Wait for the running jobs to finish before benchmarking".to_string());
+    }
+
+    let (job_id, cancel) = registry.register();
+    let scratch_dir = std::env::temp_dir().join(format!("image-benchmark-{}-{}", std::process::id(), job_id));
+
+    let result = tokio::task::spawn_blocking(move || {
+        let report = run_benchmark(&paths, &backends, iterations, &scratch_dir, &cancel);
+        let _ = std::fs::remove_dir_all(&scratch_dir);
+
+        let mut report = report?;
+        if let Some(export_path) = export_path {
+            export_report(&report, Path::new(&export_path))?;
+            report.export_path = Some(export_path);
+        }
+
+        Ok(report)
+    })
+    .await;
+    registry.finish(job_id);
+
+    result.map_err(|e| format!("Benchmark task failed: {}", e))?
+}
@@ -0,0 +1,492 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use serde::Deserialize;
+use serde_json::json;
+use tauri::State;
+
+use crate::global::IMAGE_CACHE_DIR;
+use crate::image::cache_index::CacheIndex;
+use crate::image::error::ImportEntry;
+use crate::image::icc::{self, IccProfile};
+use crate::image::pages;
+
+pub const DEFAULT_CACHE_LIMIT: u64 = 20 * 1024 * 1024 * 1024;
+
+const CACHE_DIRS: [&str; 3] = ["highres", "lowres", "tiles"];
+
+/// Prefix of hashes the Python backend gives the files it imports.
+const PYTHON_HASH_PREFIX: &str = "py-";
+
+/// Which cached copies `clear_cache` removes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheScope {
+    All,
+    Highres,
+    Lowres,
+    Tiles,
+}
+
+impl CacheScope {
+    fn includes(&self, cache_dir: &str) -> bool {
+        match self {
+            CacheScope::All => true,
+            CacheScope::Highres => cache_dir == "highres",
+            CacheScope::Lowres => cache_dir == "lowres",
+            CacheScope::Tiles => cache_dir == "tiles",
+        }
+    }
+}
+
+/// Images the UI currently has open, and the ones an import is writing right now. Neither
+/// is ever evicted or cleared.
+#[derive(Debug, Clone, Default)]
+pub struct CachePins {
+    pins: Arc<Mutex<HashMap<String, usize>>>,
+    writing: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+/// Keeps an image out of eviction and clearing while its copies are written, until dropped.
+pub struct WritingGuard {
+    writing: Arc<Mutex<HashMap<String, usize>>>,
+    hash: String,
+}
+
+impl Drop for WritingGuard {
+    fn drop(&mut self) {
+        let mut writing = self.writing.lock().unwrap();
+        if let Some(count) = writing.get_mut(&self.hash) {
+            *count -= 1;
+            if *count == 0 {
+                writing.remove(&self.hash);
+            }
+        }
+    }
+}
+
+impl CachePins {
+    pub fn pin(&self, hashes: &[String]) {
+        let mut pins = self.pins.lock().unwrap();
+        for hash in hashes {
+            *pins.entry(hash.clone()).or_default() += 1;
+        }
+    }
+
+    pub fn unpin(&self, hashes: &[String]) {
+        let mut pins = self.pins.lock().unwrap();
+        for hash in hashes {
+            if let Some(count) = pins.get_mut(hash) {
+                *count -= 1;
+                if *count == 0 {
+                    pins.remove(hash);
+                }
+            }
+        }
+    }
+
+    pub fn pinned(&self) -> HashSet<String> {
+        self.pins.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Marks `hash` as being written, taken before the first copy of it is written.
+    pub fn writing(&self, hash: &str) -> WritingGuard {
+        *self.writing.lock().unwrap().entry(hash.to_string()).or_default() += 1;
+
+        WritingGuard {
+            writing: self.writing.clone(),
+            hash: hash.to_string(),
+        }
+    }
+
+    fn being_written(&self) -> HashSet<String> {
+        self.writing.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Pinned images together with the ones being written.
+    fn protected(&self) -> HashSet<String> {
+        let mut protected = self.pinned();
+        protected.extend(self.being_written());
+        protected
+    }
+}
+
+/// Checks that `hash` is one the importers hand out: 64 lowercase hex digits, optionally
+/// behind the Python backend's prefix. Hashes are joined into cache paths, so anything
+/// else coming in over IPC is rejected before it can point outside the cache.
+pub fn validate_hash(hash: &str) -> Result<(), String> {
+    let digest = hash.strip_prefix(PYTHON_HASH_PREFIX).unwrap_or(hash);
+    let valid = digest.len() == 64 && digest.bytes().all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("{:?} is not an image hash", hash))
+    }
+}
+
+/// [`validate_hash`] for every hash in `hashes`.
+pub fn validate_hashes(hashes: &[String]) -> Result<(), String> {
+    hashes.iter().try_for_each(|hash| validate_hash(hash))
+}
+
+/// Everything stored on disk for one image hash.
+#[derive(Debug, Default)]
+struct CachedImage {
+    files: Vec<(&'static str, PathBuf)>,
+    /// Bytes per cache directory
+    sizes: HashMap<&'static str, u64>,
+    last_accessed: u64,
+}
+
+impl CachedImage {
+    fn size(&self) -> u64 {
+        self.sizes.values().sum()
+    }
+}
+
+/// Decodes the cached high-res copy of an image at its full bit depth.
+pub fn open_highres(hash: &str) -> Result<DynamicImage, String> {
+    open_highres_page(hash, 0)
+}
+
+/// Same as [`open_highres`] for one page of a multi-page document or animation.
+pub fn open_highres_page(hash: &str, page: usize) -> Result<DynamicImage, String> {
+    highres_reader(hash, page)?
+        .decode()
+        .map_err(|e| format!("Failed to decode cached image: {}", e))
+}
+
+/// Opens the high-res copy for export, converted from the profile it was cached with to
+/// sRGB since the exported file carries no profile.
+pub fn open_highres_srgb(hash: &str) -> Result<DynamicImage, String> {
+    let mut decoder = highres_reader(hash, 0)?
+        .into_decoder()
+        .map_err(|e| format!("Failed to decode cached image: {}", e))?;
+    let profile = decoder.icc_profile().ok().flatten().map(IccProfile::new);
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|e| format!("Failed to decode cached image: {}", e))?;
+
+    if let Some(profile) = profile {
+        icc::convert_image_to_srgb(&mut image, &profile);
+    }
+    Ok(image)
+}
+
+fn highres_reader(hash: &str, page: usize) -> Result<ImageReader<BufReader<File>>, String> {
+    let highres_dir = IMAGE_CACHE_DIR.lock().unwrap().join("highres");
+    let highres_path = match page {
+        0 => highres_dir.join(hash.to_string() + ".tiff"),
+        page => highres_dir.join(hash).join(pages::page_name(page) + ".tiff"),
+    };
+
+    let mut reader = ImageReader::open(&highres_path)
+        .map_err(|e| format!("Failed to open cached image: {}", e))?
+        .with_guessed_format()
+        .map_err(|e| format!("Failed to guess image format: {}", e))?;
+
+    reader.no_limits();
+    Ok(reader)
+}
+
+fn path_size(path: &Path) -> u64 {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return 0;
+    };
+
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    std::fs::read_dir(path)
+        .map(|entries| entries.flatten().map(|entry| path_size(&entry.path())).sum())
+        .unwrap_or_default()
+}
+
+fn modified_secs(path: &Path) -> u64 {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+// Every cached copy is named after the hash, e.g. `<hash>.tiff` or a `<hash>/` directory
+fn hash_of(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    name.split('.').next().map(str::to_string)
+}
+
+// Only the top level of each cache directory is listed. Indexed images are sized from what
+// the index measured, the rest are walked and measured once they are indexed and done
+fn collect_cached_images(
+    image_cache_dir: &Path,
+    index: &CacheIndex,
+    pins: &CachePins,
+) -> Result<HashMap<String, CachedImage>, String> {
+    let mut images: HashMap<String, CachedImage> = HashMap::new();
+
+    for cache_dir in CACHE_DIRS {
+        let Ok(entries) = std::fs::read_dir(image_cache_dir.join(cache_dir)) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if let Some(hash) = hash_of(&path) {
+                images.entry(hash).or_default().files.push((cache_dir, path));
+            }
+        }
+    }
+
+    let access_times = index.access_times()?;
+    let indexed = index.hashes()?;
+    let mut measured = index.sizes()?;
+    let being_written = pins.being_written();
+
+    for (hash, image) in images.iter_mut() {
+        match measured.remove(hash) {
+            Some(dir_sizes) => {
+                image.sizes = CACHE_DIRS
+                    .iter()
+                    .map(|cache_dir| (*cache_dir, dir_sizes.get(*cache_dir).copied().unwrap_or_default()))
+                    .collect();
+            }
+            None => {
+                for (cache_dir, path) in &image.files {
+                    *image.sizes.entry(cache_dir).or_default() += path_size(path);
+                }
+
+                if indexed.contains(hash) && !being_written.contains(hash) {
+                    let dir_sizes = image.sizes.iter().map(|(cache_dir, size)| (cache_dir.to_string(), *size)).collect();
+                    if let Err(e) = index.record_sizes(hash, &dir_sizes) {
+                        println!("Failed to record the size of {} in the cache index: {}", hash, e);
+                    }
+                }
+            }
+        }
+
+        // Files the index does not know about, e.g. ones the Python backend is still writing,
+        // count as used when they were last written rather than as the oldest
+        image.last_accessed = match access_times.get(hash) {
+            Some(accessed) => *accessed,
+            None => image.files.iter().map(|(_, path)| modified_secs(path)).max().unwrap_or_default(),
+        };
+    }
+
+    Ok(images)
+}
+
+fn remove_files(hash: &str, image: &CachedImage, scope: CacheScope, index: &CacheIndex) -> u64 {
+    let mut failed = HashSet::new();
+
+    for (cache_dir, path) in &image.files {
+        if !scope.includes(cache_dir) {
+            continue;
+        }
+
+        let removed = if path.is_dir() {
+            std::fs::remove_dir_all(path)
+        } else {
+            std::fs::remove_file(path)
+        };
+
+        if let Err(e) = removed {
+            println!("Failed to remove {:?}: {}", path, e);
+            failed.insert(*cache_dir);
+        }
+    }
+
+    // Without the high-res copy nothing else can be regenerated, so the image is gone from the index
+    let forgotten = if scope.includes("highres") {
+        index.remove_entry(hash)
+    } else {
+        index.forget_sizes(hash)
+    };
+    if let Err(e) = forgotten {
+        println!("Failed to remove {} from the cache index: {}", hash, e);
+    }
+
+    image
+        .sizes
+        .iter()
+        .filter(|(cache_dir, _)| scope.includes(cache_dir) && !failed.contains(*cache_dir))
+        .map(|(_, size)| size)
+        .sum()
+}
+
+/// Removes least recently used images until the cache fits its limit again. Pinned images,
+/// images being written and the ones in `protected` (e.g. the batch just imported) are kept.
+pub fn evict_to_limit(
+    image_cache_dir: &Path,
+    index: &CacheIndex,
+    pins: &CachePins,
+    protected: &HashSet<String>,
+) -> Result<serde_json::Value, String> {
+    let limit = index.cache_limit()?.unwrap_or(DEFAULT_CACHE_LIMIT);
+    let images = collect_cached_images(image_cache_dir, index, pins)?;
+    let pinned = pins.protected();
+
+    let mut total: u64 = images.values().map(CachedImage::size).sum();
+
+    let mut candidates: Vec<(&String, &CachedImage)> = images
+        .iter()
+        .filter(|(hash, _)| !pinned.contains(*hash) && !protected.contains(*hash))
+        .collect();
+    candidates.sort_by_key(|(_, image)| image.last_accessed);
+
+    let mut evicted = Vec::new();
+    let mut freed = 0;
+    for (hash, image) in candidates {
+        if total <= limit {
+            break;
+        }
+
+        let removed = remove_files(hash, image, CacheScope::All, index);
+        total = total.saturating_sub(removed);
+        freed += removed;
+        evicted.push(hash.clone());
+    }
+
+    Ok(json!({
+        "evicted": evicted,
+        "freed_bytes": freed,
+        "total_bytes": total,
+        "limit_bytes": limit
+    }))
+}
+
+/// [`evict_to_limit`] after an import, keeping every image it returned. `on_event` gets an
+/// `evicted` event when anything was removed.
+pub fn evict_after_import(
+    image_cache_dir: &Path,
+    index: &CacheIndex,
+    pins: &CachePins,
+    results: &[ImportEntry],
+    on_event: impl Fn(serde_json::Value),
+) {
+    // The images just imported are what the user is looking at, never evict those
+    let imported: HashSet<String> = results
+        .iter()
+        .filter_map(|entry| match entry {
+            ImportEntry::Ok(output) => output["hash"].as_str().map(str::to_string),
+            ImportEntry::Error(_) => None,
+        })
+        .collect();
+
+    match evict_to_limit(image_cache_dir, index, pins, &imported) {
+        Ok(eviction) if eviction["freed_bytes"].as_u64().unwrap_or_default() > 0 => {
+            on_event(json!({ "event": "evicted", "data": eviction }));
+        }
+        Ok(_) => {}
+        Err(e) => println!("Cache eviction failed: {}", e),
+    }
+}
+
+fn cache_stats(image_cache_dir: &Path, index: &CacheIndex, pins: &CachePins) -> Result<serde_json::Value, String> {
+    let images = collect_cached_images(image_cache_dir, index, pins)?;
+
+    let mut dir_sizes: HashMap<&str, u64> = HashMap::new();
+    for image in images.values() {
+        for (cache_dir, size) in &image.sizes {
+            *dir_sizes.entry(cache_dir).or_default() += size;
+        }
+    }
+
+    Ok(json!({
+        "image_count": images.len(),
+        "pinned_count": pins.pinned().len(),
+        "total_bytes": dir_sizes.values().sum::<u64>(),
+        "limit_bytes": index.cache_limit()?.unwrap_or(DEFAULT_CACHE_LIMIT),
+        "highres_bytes": dir_sizes.get("highres").copied().unwrap_or_default(),
+        "lowres_bytes": dir_sizes.get("lowres").copied().unwrap_or_default(),
+        "tiles_bytes": dir_sizes.get("tiles").copied().unwrap_or_default()
+    }))
+}
+
+fn clear(image_cache_dir: &Path, index: &CacheIndex, pins: &CachePins, scope: CacheScope) -> Result<serde_json::Value, String> {
+    let images = collect_cached_images(image_cache_dir, index, pins)?;
+    let pinned = pins.protected();
+
+    let mut cleared = Vec::new();
+    let mut freed = 0;
+    for (hash, image) in &images {
+        if pinned.contains(hash) {
+            continue;
+        }
+
+        freed += remove_files(hash, image, scope, index);
+        cleared.push(hash.clone());
+    }
+
+    Ok(json!({
+        "cleared": cleared,
+        "freed_bytes": freed
+    }))
+}
+
+#[tauri::command]
+pub async fn get_cache_stats(
+    index: State<'_, CacheIndex>,
+    pins: State<'_, CachePins>,
+) -> Result<serde_json::Value, String> {
+    let image_cache_dir = IMAGE_CACHE_DIR.lock().unwrap().clone();
+    let (index, pins) = (index.inner().clone(), pins.inner().clone());
+
+    tokio::task::spawn_blocking(move || cache_stats(&image_cache_dir, &index, &pins))
+        .await
+        .map_err(|e| format!("Cache stats task failed: {}", e))?
+}
+
+#[tauri::command]
+pub async fn clear_cache(
+    scope: CacheScope,
+    index: State<'_, CacheIndex>,
+    pins: State<'_, CachePins>,
+) -> Result<serde_json::Value, String> {
+    let image_cache_dir = IMAGE_CACHE_DIR.lock().unwrap().clone();
+    let (index, pins) = (index.inner().clone(), pins.inner().clone());
+
+    tokio::task::spawn_blocking(move || clear(&image_cache_dir, &index, &pins, scope))
+        .await
+        .map_err(|e| format!("Clear cache task failed: {}", e))?
+}
+
+#[tauri::command]
+pub async fn set_cache_limit(
+    limit_bytes: u64,
+    index: State<'_, CacheIndex>,
+    pins: State<'_, CachePins>,
+) -> Result<serde_json::Value, String> {
+    let image_cache_dir = IMAGE_CACHE_DIR.lock().unwrap().clone();
+    let (index, pins) = (index.inner().clone(), pins.inner().clone());
+
+    index.set_cache_limit(limit_bytes)?;
+
+    tokio::task::spawn_blocking(move || evict_to_limit(&image_cache_dir, &index, &pins, &HashSet::new()))
+        .await
+        .map_err(|e| format!("Cache eviction task failed: {}", e))?
+}
+
+#[tauri::command]
+pub fn pin_images(
+    hashes: Vec<String>,
+    index: State<'_, CacheIndex>,
+    pins: State<'_, CachePins>,
+) -> Result<(), String> {
+    validate_hashes(&hashes)?;
+    pins.pin(&hashes);
+    index.touch(&hashes)
+}
+
+#[tauri::command]
+pub fn unpin_images(hashes: Vec<String>, pins: State<'_, CachePins>) -> Result<(), String> {
+    validate_hashes(&hashes)?;
+    pins.unpin(&hashes);
+    Ok(())
+}
@@ -0,0 +1,374 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use redb::{Database, ReadableTable, TableDefinition};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+use xxhash_rust::xxh3::Xxh3;
+
+const HASH_BUFFER_SIZE: usize = 1024 * 1024;
+
+// Source path -> SourceRecord (JSON)
+const SOURCES: TableDefinition<&str, &[u8]> = TableDefinition::new("sources");
+// Fast file-content hash -> pixel hash, so moved or copied files still hit the cache
+const CONTENTS: TableDefinition<&str, &str> = TableDefinition::new("contents");
+// Pixel hash -> import result (JSON), the same object `import_images` returns for the image
+const ENTRIES: TableDefinition<&str, &[u8]> = TableDefinition::new("entries");
+// Pixel hash -> last time the image was imported or opened (unix seconds), for LRU eviction
+const ACCESS: TableDefinition<&str, u64> = TableDefinition::new("access");
+// Pixel hash -> bytes its copies take in each cache directory (JSON), measured once per change
+// so eviction does not walk every tile
+const SIZES: TableDefinition<&str, &[u8]> = TableDefinition::new("sizes");
+const SETTINGS: TableDefinition<&str, u64> = TableDefinition::new("settings");
+
+// Reads within this many seconds of the last one are not written again, tiles are fetched by the hundred
+const TOUCH_INTERVAL_SECS: u64 = 60;
+
+const CACHE_LIMIT_KEY: &str = "cache_limit_bytes";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SourceRecord {
+    size: u64,
+    modified: u64,
+    content_hash: String,
+    hash: String,
+}
+
+/// What identifies a source file on disk without decoding it.
+pub struct SourceFingerprint {
+    path: String,
+    size: u64,
+    modified: u64,
+    content_hash: Option<String>,
+}
+
+impl SourceFingerprint {
+    pub fn read(path: &str) -> std::io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_default();
+
+        Ok(Self {
+            path: path.to_string(),
+            size: metadata.len(),
+            modified,
+            content_hash: None,
+        })
+    }
+
+    /// xxh3 over the raw file bytes, computed once and only when the path lookup misses.
+    pub fn content_hash(&mut self) -> std::io::Result<&str> {
+        if self.content_hash.is_none() {
+            let mut reader = BufReader::with_capacity(HASH_BUFFER_SIZE, File::open(&self.path)?);
+            let mut hasher = Xxh3::new();
+            let mut buffer = vec![0; HASH_BUFFER_SIZE];
+
+            loop {
+                let n = reader.read(&mut buffer)?;
+                if n == 0 { break; }
+                hasher.update(&buffer[..n]);
+            }
+
+            self.content_hash = Some(format!("{:032x}", hasher.digest128()));
+        }
+
+        Ok(self.content_hash.as_deref().unwrap())
+    }
+}
+
+/// Persistent map from source files to the pixel hash their cached copies are stored under.
+#[derive(Clone)]
+pub struct CacheIndex {
+    db: Arc<Database>,
+    // Last access written per hash in this session
+    touched: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl CacheIndex {
+    pub fn open(path: &Path) -> Result<Self, redb::Error> {
+        let db = Database::create(path)?;
+
+        // Create the tables up front so readers never see a missing table
+        let write = db.begin_write()?;
+        write.open_table(SOURCES)?;
+        write.open_table(CONTENTS)?;
+        write.open_table(ENTRIES)?;
+        write.open_table(ACCESS)?;
+        write.open_table(SIZES)?;
+        write.open_table(SETTINGS)?;
+        write.commit()?;
+
+        Ok(Self {
+            db: Arc::new(db),
+            touched: Arc::default(),
+        })
+    }
+
+    /// Pixel hash previously recorded for this file, if any. The caller still has to check
+    /// that the cached copies exist, the index only knows what was imported. Nothing is
+    /// written: a file found by its contents (moved, copied or touched) is only recorded under
+    /// its new path and times once the caller [`record`](Self::record)s it.
+    pub fn lookup(&self, fingerprint: &mut SourceFingerprint) -> Result<Option<String>, String> {
+        let read = self.db.begin_read().map_err(|e| e.to_string())?;
+
+        let sources = read.open_table(SOURCES).map_err(|e| e.to_string())?;
+        if let Some(value) = sources.get(fingerprint.path.as_str()).map_err(|e| e.to_string())? {
+            if let Ok(record) = serde_json::from_slice::<SourceRecord>(value.value()) {
+                if record.size == fingerprint.size && record.modified == fingerprint.modified {
+                    return Ok(Some(record.hash));
+                }
+            }
+        }
+
+        let content_hash = fingerprint
+            .content_hash()
+            .map_err(|e| format!("Failed to hash source file: {}", e))?;
+
+        let contents = read.open_table(CONTENTS).map_err(|e| e.to_string())?;
+        let hash = contents
+            .get(content_hash)
+            .map_err(|e| e.to_string())?
+            .map(|value| value.value().to_string());
+
+        Ok(hash)
+    }
+
+    pub fn record(&self, fingerprint: &mut SourceFingerprint, hash: &str) -> Result<(), String> {
+        let content_hash = fingerprint
+            .content_hash()
+            .map_err(|e| format!("Failed to hash source file: {}", e))?
+            .to_string();
+
+        let record = SourceRecord {
+            size: fingerprint.size,
+            modified: fingerprint.modified,
+            content_hash: content_hash.clone(),
+            hash: hash.to_string(),
+        };
+        let value = serde_json::to_vec(&record).map_err(|e| e.to_string())?;
+
+        let write = self.db.begin_write().map_err(|e| e.to_string())?;
+        {
+            let mut sources = write.open_table(SOURCES).map_err(|e| e.to_string())?;
+            sources.insert(fingerprint.path.as_str(), value.as_slice()).map_err(|e| e.to_string())?;
+
+            let mut contents = write.open_table(CONTENTS).map_err(|e| e.to_string())?;
+            contents.insert(content_hash.as_str(), hash).map_err(|e| e.to_string())?;
+        }
+        write.commit().map_err(|e| e.to_string())
+    }
+
+    pub fn entry(&self, hash: &str) -> Result<Option<serde_json::Value>, String> {
+        let read = self.db.begin_read().map_err(|e| e.to_string())?;
+        let entries = read.open_table(ENTRIES).map_err(|e| e.to_string())?;
+
+        let entry = entries.get(hash).map_err(|e| e.to_string())?;
+        match entry {
+            Some(value) => serde_json::from_slice(value.value()).map(Some).map_err(|e| e.to_string()),
+            None => Ok(None),
+        }
+    }
+
+    /// Stores the result of an import. Re-importing keeps the date of the first import.
+    pub fn record_entry(&self, output: &mut serde_json::Value) -> Result<(), String> {
+        let hash = output["hash"].as_str().ok_or("Import result has no hash")?.to_string();
+
+        // Read in the same transaction, so a concurrent import cannot reset the date in between
+        let write = self.db.begin_write().map_err(|e| e.to_string())?;
+        {
+            let mut entries = write.open_table(ENTRIES).map_err(|e| e.to_string())?;
+            let previous = entries
+                .get(hash.as_str())
+                .map_err(|e| e.to_string())?
+                .and_then(|value| serde_json::from_slice::<serde_json::Value>(value.value()).ok());
+
+            output["imported_at"] = match previous {
+                Some(previous) if previous["imported_at"].is_u64() => previous["imported_at"].clone(),
+                _ => serde_json::json!(unix_timestamp()),
+            };
+            let value = serde_json::to_vec(output).map_err(|e| e.to_string())?;
+            entries.insert(hash.as_str(), value.as_slice()).map_err(|e| e.to_string())?;
+
+            let mut access = write.open_table(ACCESS).map_err(|e| e.to_string())?;
+            access.insert(hash.as_str(), unix_timestamp()).map_err(|e| e.to_string())?;
+
+            // The import may have added or replaced copies, they are measured again
+            let mut sizes = write.open_table(SIZES).map_err(|e| e.to_string())?;
+            sizes.remove(hash.as_str()).map_err(|e| e.to_string())?;
+        }
+        write.commit().map_err(|e| e.to_string())
+    }
+
+    pub fn remove_entry(&self, hash: &str) -> Result<(), String> {
+        let write = self.db.begin_write().map_err(|e| e.to_string())?;
+        {
+            let mut entries = write.open_table(ENTRIES).map_err(|e| e.to_string())?;
+            entries.remove(hash).map_err(|e| e.to_string())?;
+
+            let mut access = write.open_table(ACCESS).map_err(|e| e.to_string())?;
+            access.remove(hash).map_err(|e| e.to_string())?;
+
+            let mut sizes = write.open_table(SIZES).map_err(|e| e.to_string())?;
+            sizes.remove(hash).map_err(|e| e.to_string())?;
+        }
+        write.commit().map_err(|e| e.to_string())
+    }
+
+    /// Marks images as used right now so eviction keeps them around longer. Called on every
+    /// read of a cached copy, so an image touched less than a minute ago is not written again.
+    pub fn touch(&self, hashes: &[String]) -> Result<(), String> {
+        let now = unix_timestamp();
+        let stale: Vec<&String> = {
+            let mut touched = self.touched.lock().unwrap();
+            hashes
+                .iter()
+                .filter(|hash| {
+                    let fresh = touched.get(*hash).is_some_and(|last| now.saturating_sub(*last) < TOUCH_INTERVAL_SECS);
+                    if !fresh {
+                        touched.insert(hash.to_string(), now);
+                    }
+                    !fresh
+                })
+                .collect()
+        };
+        if stale.is_empty() {
+            return Ok(());
+        }
+
+        let write = self.db.begin_write().map_err(|e| e.to_string())?;
+        {
+            let mut access = write.open_table(ACCESS).map_err(|e| e.to_string())?;
+            for hash in stale {
+                access.insert(hash.as_str(), now).map_err(|e| e.to_string())?;
+            }
+        }
+        write.commit().map_err(|e| e.to_string())
+    }
+
+    /// [`touch`](Self::touch) for an image that is being read. Failing to record the access
+    /// is only logged, it is no reason to fail the read.
+    pub fn record_access(&self, hash: &str) {
+        if let Err(e) = self.touch(&[hash.to_string()]) {
+            println!("Failed to record access to {} in the cache index: {}", hash, e);
+        }
+    }
+
+    /// Hashes that have an import result recorded.
+    pub fn hashes(&self) -> Result<HashSet<String>, String> {
+        let read = self.db.begin_read().map_err(|e| e.to_string())?;
+        let entries = read.open_table(ENTRIES).map_err(|e| e.to_string())?;
+
+        let mut hashes = HashSet::new();
+        for entry in entries.iter().map_err(|e| e.to_string())? {
+            let (hash, _) = entry.map_err(|e| e.to_string())?;
+            hashes.insert(hash.value().to_string());
+        }
+
+        Ok(hashes)
+    }
+
+    /// Bytes each image's copies take per cache directory, for the images measured since
+    /// they last changed.
+    pub fn sizes(&self) -> Result<HashMap<String, HashMap<String, u64>>, String> {
+        let read = self.db.begin_read().map_err(|e| e.to_string())?;
+        let sizes = read.open_table(SIZES).map_err(|e| e.to_string())?;
+
+        let mut measured = HashMap::new();
+        for entry in sizes.iter().map_err(|e| e.to_string())? {
+            let (hash, value) = entry.map_err(|e| e.to_string())?;
+            if let Ok(dir_sizes) = serde_json::from_slice(value.value()) {
+                measured.insert(hash.value().to_string(), dir_sizes);
+            }
+        }
+
+        Ok(measured)
+    }
+
+    pub fn record_sizes(&self, hash: &str, dir_sizes: &HashMap<String, u64>) -> Result<(), String> {
+        let value = serde_json::to_vec(dir_sizes).map_err(|e| e.to_string())?;
+
+        let write = self.db.begin_write().map_err(|e| e.to_string())?;
+        {
+            let mut sizes = write.open_table(SIZES).map_err(|e| e.to_string())?;
+            sizes.insert(hash, value.as_slice()).map_err(|e| e.to_string())?;
+        }
+        write.commit().map_err(|e| e.to_string())
+    }
+
+    /// Drops the measured sizes of `hash` after some of its copies were removed or added.
+    pub fn forget_sizes(&self, hash: &str) -> Result<(), String> {
+        let write = self.db.begin_write().map_err(|e| e.to_string())?;
+        {
+            let mut sizes = write.open_table(SIZES).map_err(|e| e.to_string())?;
+            sizes.remove(hash).map_err(|e| e.to_string())?;
+        }
+        write.commit().map_err(|e| e.to_string())
+    }
+
+    pub fn access_times(&self) -> Result<HashMap<String, u64>, String> {
+        let read = self.db.begin_read().map_err(|e| e.to_string())?;
+        let access = read.open_table(ACCESS).map_err(|e| e.to_string())?;
+
+        let mut times = HashMap::new();
+        for entry in access.iter().map_err(|e| e.to_string())? {
+            let (hash, accessed) = entry.map_err(|e| e.to_string())?;
+            times.insert(hash.value().to_string(), accessed.value());
+        }
+
+        Ok(times)
+    }
+
+    pub fn cache_limit(&self) -> Result<Option<u64>, String> {
+        let read = self.db.begin_read().map_err(|e| e.to_string())?;
+        let settings = read.open_table(SETTINGS).map_err(|e| e.to_string())?;
+
+        let limit = settings.get(CACHE_LIMIT_KEY).map_err(|e| e.to_string())?;
+        Ok(limit.map(|value| value.value()))
+    }
+
+    pub fn set_cache_limit(&self, limit: u64) -> Result<(), String> {
+        let write = self.db.begin_write().map_err(|e| e.to_string())?;
+        {
+            let mut settings = write.open_table(SETTINGS).map_err(|e| e.to_string())?;
+            settings.insert(CACHE_LIMIT_KEY, limit).map_err(|e| e.to_string())?;
+        }
+        write.commit().map_err(|e| e.to_string())
+    }
+
+    /// Every cached image, most recently imported first.
+    pub fn entries(&self) -> Result<Vec<serde_json::Value>, String> {
+        let read = self.db.begin_read().map_err(|e| e.to_string())?;
+        let entries = read.open_table(ENTRIES).map_err(|e| e.to_string())?;
+
+        let mut outputs = Vec::new();
+        for entry in entries.iter().map_err(|e| e.to_string())? {
+            let (_, value) = entry.map_err(|e| e.to_string())?;
+            if let Ok(output) = serde_json::from_slice::<serde_json::Value>(value.value()) {
+                outputs.push(output);
+            }
+        }
+
+        outputs.sort_by_key(|output| std::cmp::Reverse(output["imported_at"].as_u64().unwrap_or_default()));
+
+        Ok(outputs)
+    }
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[tauri::command]
+pub fn list_cached_images(index: State<'_, CacheIndex>) -> Result<Vec<serde_json::Value>, String> {
+    index.entries()
+}
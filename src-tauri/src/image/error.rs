@@ -0,0 +1,95 @@
+use std::fmt;
+use std::path::Path;
+
+use serde::Serialize;
+
+#[derive(Debug)]
+pub enum ImportError {
+    Open(std::io::Error),
+    UnknownFormat(std::io::Error),
+    Decode(image::ImageError),
+    Rasterize(String),
+    Raw(String),
+    Resize(String),
+    Save(std::io::Error),
+    Tiles(String),
+    Metadata(std::io::Error),
+    Python(String),
+    WriteMetadata(String),
+    Cancelled,
+}
+
+impl ImportError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ImportError::Open(_) => "open",
+            ImportError::UnknownFormat(_) => "unknown_format",
+            ImportError::Decode(_) => "decode",
+            ImportError::Rasterize(_) => "rasterize",
+            ImportError::Raw(_) => "raw",
+            ImportError::Resize(_) => "resize",
+            ImportError::Save(_) => "save",
+            ImportError::Tiles(_) => "tiles",
+            ImportError::Metadata(_) => "metadata",
+            ImportError::Python(_) => "python",
+            ImportError::WriteMetadata(_) => "write_metadata",
+            ImportError::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Open(e) => write!(f, "Failed to open image: {}", e),
+            ImportError::UnknownFormat(e) => write!(f, "Failed to guess image format: {}", e),
+            ImportError::Decode(e) => write!(f, "Failed to decode image: {}", e),
+            ImportError::Rasterize(e) => write!(f, "Failed to rasterize SVG: {}", e),
+            ImportError::Raw(e) => write!(f, "Failed to develop RAW image: {}", e),
+            ImportError::Resize(e) => write!(f, "Failed to resize image: {}", e),
+            ImportError::Save(e) => write!(f, "Failed to save cached image: {}", e),
+            ImportError::Tiles(e) => write!(f, "Failed to generate tiles: {}", e),
+            ImportError::Metadata(e) => write!(f, "Failed to read metadata: {}", e),
+            ImportError::Python(e) => write!(f, "Python backend failed: {}", e),
+            ImportError::WriteMetadata(e) => write!(f, "Failed to write metadata: {}", e),
+            ImportError::Cancelled => write!(f, "Import was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// Why a single file of a batch could not be imported.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportFailure {
+    pub path: String,
+    pub filename: String,
+    pub kind: String,
+    pub message: String,
+}
+
+impl ImportFailure {
+    pub fn new(path: &str, error: &ImportError) -> Self {
+        Self {
+            path: path.to_string(),
+            filename: display_filename(path).to_string(),
+            kind: error.kind().to_string(),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// One entry per requested file, serialized as `{ "ok": {...} }` or `{ "error": {...} }`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportEntry {
+    Ok(serde_json::Value),
+    Error(ImportFailure),
+}
+
+pub fn display_filename(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
+}
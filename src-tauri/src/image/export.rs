@@ -0,0 +1,42 @@
+use std::path::PathBuf;
+
+use serde_json::json;
+use tauri::State;
+
+use crate::image::cache;
+use crate::image::cache_index::CacheIndex;
+use crate::image::formats::{self, OutputFormat};
+use crate::image::metadata_policy::{self, MetadataPolicy};
+
+/// Writes the full-resolution copy of a cached image to `path`, converted to sRGB. The format
+/// follows the file extension unless `format` is given. Which of the source's tags the copy gets is
+/// decided by `metadata_policy`, by default none.
+#[tauri::command]
+pub async fn export_image(
+    hash: String,
+    path: String,
+    format: Option<OutputFormat>,
+    quality: Option<u8>,
+    metadata_policy: Option<MetadataPolicy>,
+    index: State<'_, CacheIndex>,
+) -> Result<serde_json::Value, String> {
+    cache::validate_hash(&hash)?;
+    let output_path = PathBuf::from(&path);
+    let format = format
+        .or_else(|| OutputFormat::from_path(&output_path))
+        .ok_or_else(|| format!("Cannot tell the export format from {}", path))?;
+    let quality = quality.unwrap_or(formats::DEFAULT_QUALITY);
+    let policy = metadata_policy.unwrap_or_default();
+    let index = index.inner().clone();
+
+    tokio::task::spawn_blocking(move || {
+        let report = metadata_policy::export_with_policy(&hash, &output_path, format, quality, &policy, false, &index)?;
+
+        Ok(json!({
+            "path": output_path.to_str().unwrap(),
+            "metadata": report
+        }))
+    })
+    .await
+    .map_err(|e| format!("Export task failed: {}", e))?
+}
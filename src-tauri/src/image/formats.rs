@@ -0,0 +1,337 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use fimg::DynImage;
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::error::{DecodingError, ImageFormatHint};
+use image::{DynamicImage, ImageBuffer, ImageError, ImageFormat};
+use jxl_oxide::integration::JxlDecoder;
+use libheif_rs::{Channel, ColorSpace, CompressionFormat, EncoderQuality, HeifContext, ImageHandle, LibHeif, RgbChroma};
+use serde::{Deserialize, Serialize};
+use zune_core::bit_depth::BitDepth;
+use zune_core::colorspace::ColorSpace as ZuneColorSpace;
+use zune_core::options::EncoderOptions;
+use zune_jpegxl::JxlSimpleEncoder;
+
+use crate::image::error::ImportError;
+use crate::utilities::file_utils;
+
+pub const DEFAULT_QUALITY: u8 = 85;
+
+// 1 is the slowest and smallest, 10 the fastest
+const AVIF_SPEED: u8 = 6;
+
+/// Formats cached previews and exports can be written in. Only PNG and JPEG are shown by
+/// every webview, the others depend on the platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    Jpeg,
+    Avif,
+    Heic,
+    Jxl,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Avif => "avif",
+            OutputFormat::Heic => "heic",
+            OutputFormat::Jxl => "jxl",
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+
+        match extension.as_str() {
+            "png" => Some(OutputFormat::Png),
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "avif" => Some(OutputFormat::Avif),
+            "heic" | "heif" => Some(OutputFormat::Heic),
+            "jxl" => Some(OutputFormat::Jxl),
+            _ => None,
+        }
+    }
+
+    /// Whether a resolution can be written into files of this format afterwards. exiv2 can
+    /// neither write AVIF and HEIC nor open JPEG XL, and only PNG and JPEG have a field of
+    /// their own for it.
+    pub fn holds_resolution(&self) -> bool {
+        matches!(self, OutputFormat::Png | OutputFormat::Jpeg)
+    }
+
+    /// Settings the encoder of this format is run with besides the pixels, e.g. `q85` for
+    /// JPEG at quality 85. Empty for the lossless formats, which always write the same file.
+    pub fn encoder_key(&self, quality: u8) -> String {
+        let quality = quality.clamp(1, 100);
+
+        match self {
+            OutputFormat::Png | OutputFormat::Jxl => String::new(),
+            OutputFormat::Jpeg | OutputFormat::Heic => format!("q{}", quality),
+            OutputFormat::Avif => format!("q{}s{}", quality, AVIF_SPEED),
+        }
+    }
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extensions.iter().any(|known| extension.eq_ignore_ascii_case(known)))
+}
+
+/// HEIC, HEIF and AVIF files, which are all decoded through libheif.
+pub const HEIF_EXTENSIONS: [&str; 3] = ["heic", "heif", "avif"];
+
+pub const JXL_EXTENSIONS: [&str; 1] = ["jxl"];
+
+pub fn is_heif(path: &Path) -> bool {
+    has_extension(path, &HEIF_EXTENSIONS)
+}
+
+pub fn is_jxl(path: &Path) -> bool {
+    has_extension(path, &JXL_EXTENSIONS)
+}
+
+fn decoding_error(format: &str, error: impl std::error::Error + Send + Sync + 'static) -> ImportError {
+    ImportError::Decode(ImageError::Decoding(DecodingError::new(ImageFormatHint::Name(format.to_string()), error)))
+}
+
+fn invalid_heif() -> ImportError {
+    ImportError::Decode(ImageError::Decoding(DecodingError::from_format_hint(ImageFormatHint::Name("HEIF".to_string()))))
+}
+
+/// A HEIF container with its primary image located but not decoded yet.
+pub struct HeifSource {
+    // The handle refers into the context, keep it alive alongside
+    _context: HeifContext<'static>,
+    handle: ImageHandle,
+}
+
+impl HeifSource {
+    pub fn open(path: &Path) -> Result<Self, ImportError> {
+        let path_str = path.to_str().ok_or_else(|| {
+            ImportError::Open(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Path is not valid UTF-8"))
+        })?;
+
+        let context = HeifContext::read_from_file(path_str).map_err(|e| decoding_error("HEIF", e))?;
+        let handle = context.primary_image_handle().map_err(|e| decoding_error("HEIF", e))?;
+
+        Ok(Self { _context: context, handle })
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.handle.width(), self.handle.height())
+    }
+
+    pub fn bytes_per_pixel(&self) -> u64 {
+        let channels = if self.handle.has_alpha_channel() { 4 } else { 3 };
+        let bytes_per_channel = if self.is_high_bit_depth() { 2 } else { 1 };
+
+        channels * bytes_per_channel
+    }
+
+    /// Raw ICC profile of the primary image, if it carries one instead of only nclx colour info.
+    pub fn icc_profile(&self) -> Option<Vec<u8>> {
+        self.handle.color_profile_raw().map(|profile| profile.data)
+    }
+
+    fn is_high_bit_depth(&self) -> bool {
+        self.handle.luma_bits_per_pixel() > 8
+    }
+
+    /// Decodes the primary image. libheif already applies the rotation and mirroring
+    /// stored in the container, so the result is upright.
+    pub fn decode(&self) -> Result<DynamicImage, ImportError> {
+        let has_alpha = self.handle.has_alpha_channel();
+        let high_bit_depth = self.is_high_bit_depth();
+
+        let chroma = match (high_bit_depth, has_alpha) {
+            (false, false) => RgbChroma::Rgb,
+            (false, true) => RgbChroma::Rgba,
+            (true, false) => RgbChroma::HdrRgbLe,
+            (true, true) => RgbChroma::HdrRgbaLe,
+        };
+
+        let image = LibHeif::new()
+            .decode(&self.handle, ColorSpace::Rgb(chroma), None)
+            .map_err(|e| decoding_error("HEIF", e))?;
+
+        let planes = image.planes();
+        let plane = planes.interleaved.ok_or_else(invalid_heif)?;
+
+        let (width, height) = (plane.width, plane.height);
+        let channels = if has_alpha { 4 } else { 3 };
+        let row_bytes = width as usize * channels * if high_bit_depth { 2 } else { 1 };
+
+        // Rows are padded to `stride`, copy only the pixels
+        let mut bytes = Vec::with_capacity(row_bytes * height as usize);
+        for row in 0..height as usize {
+            let start = row * plane.stride;
+            bytes.extend_from_slice(&plane.data[start..start + row_bytes]);
+        }
+
+        if !high_bit_depth {
+            return if has_alpha {
+                ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageRgba8).ok_or_else(invalid_heif)
+            } else {
+                ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageRgb8).ok_or_else(invalid_heif)
+            };
+        }
+
+        // 10 and 12 bit samples are scaled up to use the whole 16-bit range
+        let shift = 16 - plane.bits_per_pixel.clamp(9, 16) as u32;
+        let samples: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|sample| u16::from_le_bytes([sample[0], sample[1]]) << shift)
+            .collect();
+
+        if has_alpha {
+            ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgba16).ok_or_else(invalid_heif)
+        } else {
+            ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgb16).ok_or_else(invalid_heif)
+        }
+    }
+}
+
+/// Reads the JPEG XL header, the pixels are decoded once the decoder is handed to
+/// `DynamicImage::from_decoder`. Orientation from the header is applied while rendering.
+pub fn open_jxl(path: &Path) -> Result<JxlDecoder<BufReader<File>>, ImportError> {
+    let file = File::open(path).map_err(ImportError::Open)?;
+
+    JxlDecoder::new(BufReader::new(file)).map_err(ImportError::Decode)
+}
+
+/// Writes `image` as `format`. PNG and JPEG XL keep 16-bit samples, the lossy formats
+/// are written as 8-bit and JPEG drops alpha. `quality` (1-100) is ignored by the
+/// lossless formats.
+pub fn write_image(image: &DynamicImage, format: OutputFormat, quality: u8, output_path: &Path) -> std::io::Result<()> {
+    let quality = quality.clamp(1, 100);
+
+    match format {
+        OutputFormat::Png => image
+            .save_with_format(output_path, ImageFormat::Png)
+            .map_err(std::io::Error::other),
+        OutputFormat::Jpeg => {
+            let writer = BufWriter::new(File::create(output_path)?);
+            let encoder = JpegEncoder::new_with_quality(writer, quality);
+
+            let result = if image.color().has_color() {
+                DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder)
+            } else {
+                DynamicImage::ImageLuma8(image.to_luma8()).write_with_encoder(encoder)
+            };
+            result.map_err(std::io::Error::other)
+        }
+        OutputFormat::Avif => {
+            let writer = BufWriter::new(File::create(output_path)?);
+            let encoder = AvifEncoder::new_with_speed_quality(writer, AVIF_SPEED, quality);
+
+            to_8_bit(image).write_with_encoder(encoder).map_err(std::io::Error::other)
+        }
+        OutputFormat::Heic => write_heic(&to_8_bit(image).to_rgba8(), quality, output_path),
+        OutputFormat::Jxl => write_jxl(image, output_path),
+    }
+}
+
+/// Writes a cached 8-bit preview, which is kept in fimg's image type.
+pub fn write_preview<T: AsRef<[u8]>>(image: &DynImage<T>, format: OutputFormat, quality: u8, output_path: &Path) -> std::io::Result<()> {
+    if format == OutputFormat::Png {
+        return file_utils::write_dyn_png(image, output_path);
+    }
+
+    write_image(&to_dynamic_image(image)?, format, quality, output_path)
+}
+
+/// Copies an 8-bit image in fimg's type back into the `image` crate's.
+pub fn to_dynamic_image<T: AsRef<[u8]>>(image: &DynImage<T>) -> std::io::Result<DynamicImage> {
+    let (width, height) = (image.width(), image.height());
+    let bytes = image.bytes().to_vec();
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "Preview pixels do not match its size");
+
+    match image {
+        DynImage::Y(_) => ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageLuma8),
+        DynImage::Ya(_) => ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageLumaA8),
+        DynImage::Rgb(_) => ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageRgb8),
+        DynImage::Rgba(_) => ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageRgba8),
+    }
+    .ok_or_else(invalid)
+}
+
+fn to_8_bit(image: &DynamicImage) -> DynamicImage {
+    match (image.color().has_color(), image.color().has_alpha()) {
+        (false, false) => DynamicImage::ImageLuma8(image.to_luma8()),
+        (false, true) => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+        (true, false) => DynamicImage::ImageRgb8(image.to_rgb8()),
+        (true, true) => DynamicImage::ImageRgba8(image.to_rgba8()),
+    }
+}
+
+fn write_heic(image: &image::RgbaImage, quality: u8, output_path: &Path) -> std::io::Result<()> {
+    let (width, height) = image.dimensions();
+
+    let mut heif_image = libheif_rs::Image::new(width, height, ColorSpace::Rgb(RgbChroma::Rgba)).map_err(std::io::Error::other)?;
+    heif_image.create_plane(Channel::Interleaved, width, height, 8).map_err(std::io::Error::other)?;
+
+    {
+        let planes = heif_image.planes_mut();
+        let plane = planes
+            .interleaved
+            .ok_or_else(|| std::io::Error::other("libheif did not create an interleaved plane"))?;
+
+        let row_bytes = width as usize * 4;
+        for (row, pixels) in image.as_raw().chunks_exact(row_bytes).enumerate() {
+            let start = row * plane.stride;
+            plane.data[start..start + row_bytes].copy_from_slice(pixels);
+        }
+    }
+
+    let lib_heif = LibHeif::new();
+    let mut encoder = lib_heif.encoder_for_format(CompressionFormat::Hevc).map_err(std::io::Error::other)?;
+    encoder.set_quality(EncoderQuality::Lossy(quality)).map_err(std::io::Error::other)?;
+
+    let mut context = HeifContext::new().map_err(std::io::Error::other)?;
+    context.encode_image(&heif_image, &mut encoder, None).map_err(std::io::Error::other)?;
+
+    let path_str = output_path
+        .to_str()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Path is not valid UTF-8"))?;
+    context.write_to_file(path_str).map_err(std::io::Error::other)
+}
+
+// The pure Rust encoder only writes lossless JPEG XL
+fn write_jxl(image: &DynamicImage, output_path: &Path) -> std::io::Result<()> {
+    let color = image.color();
+    let depth = if color.bytes_per_pixel() / color.channel_count() > 1 { BitDepth::Sixteen } else { BitDepth::Eight };
+
+    let image = match (color.has_color(), color.has_alpha(), depth) {
+        (false, false, BitDepth::Eight) => DynamicImage::ImageLuma8(image.to_luma8()),
+        (false, true, BitDepth::Eight) => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+        (true, false, BitDepth::Eight) => DynamicImage::ImageRgb8(image.to_rgb8()),
+        (true, true, BitDepth::Eight) => DynamicImage::ImageRgba8(image.to_rgba8()),
+        (false, false, _) => DynamicImage::ImageLuma16(image.to_luma16()),
+        (false, true, _) => DynamicImage::ImageLumaA16(image.to_luma_alpha16()),
+        (true, false, _) => DynamicImage::ImageRgb16(image.to_rgb16()),
+        (true, true, _) => DynamicImage::ImageRgba16(image.to_rgba16()),
+    };
+
+    let colorspace = match (color.has_color(), color.has_alpha()) {
+        (false, false) => ZuneColorSpace::Luma,
+        (false, true) => ZuneColorSpace::LumaA,
+        (true, false) => ZuneColorSpace::RGB,
+        (true, true) => ZuneColorSpace::RGBA,
+    };
+
+    let options = EncoderOptions::new(image.width() as usize, image.height() as usize, colorspace, depth);
+    let encoded = JxlSimpleEncoder::new(image.as_bytes(), options)
+        .encode()
+        .map_err(|e| std::io::Error::other(format!("{:?}", e)))?;
+
+    std::fs::write(output_path, encoded)
+}
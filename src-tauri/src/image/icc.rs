@@ -0,0 +1,117 @@
+use fimg::{DynImage, Image};
+use image::DynamicImage;
+use lcms2::{ColorSpaceSignature, InfoType, Intent, Locale, PixelFormat, Profile, Transform};
+use serde::Serialize;
+
+/// TIFF tag the profile of the cached high-res copy is stored under.
+pub const ICC_PROFILE_TAG: u16 = 34675;
+
+// Pixels converted per call, so only a small copy is made next to the preview
+const PIXELS_PER_CHUNK: usize = 4096;
+
+/// An ICC profile embedded in a source file. The bytes are kept as they are even when lcms
+/// cannot parse them, so the cached copy still carries the profile the file came with.
+#[derive(Debug, Clone, Serialize)]
+pub struct IccProfile {
+    /// Description the profile gives itself, e.g. "Adobe RGB (1998)"
+    pub name: Option<String>,
+    /// `rgb`, `gray`, `cmyk` or `other`, `None` when the profile could not be parsed
+    pub color_space: Option<&'static str>,
+    pub size: usize,
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+impl IccProfile {
+    pub fn new(data: Vec<u8>) -> Self {
+        let profile = Profile::new_icc(&data).ok();
+
+        Self {
+            name: profile.as_ref().and_then(|profile| profile.info(InfoType::Description, Locale::none())),
+            color_space: profile.as_ref().map(|profile| match profile.color_space() {
+                ColorSpaceSignature::RgbData => "rgb",
+                ColorSpaceSignature::GrayData => "gray",
+                ColorSpaceSignature::CmykData => "cmyk",
+                _ => "other",
+            }),
+            size: data.len(),
+            data,
+        }
+    }
+
+    /// Profiles that describe sRGB itself, previews made with them need no conversion.
+    pub fn is_srgb(&self) -> bool {
+        self.name.as_deref().is_some_and(|name| name.contains("sRGB"))
+    }
+}
+
+fn convert<T: Copy, const N: usize>(pixels: &mut [T], transform: &Transform<[T; N], [T; N]>) {
+    let mut chunk = Vec::with_capacity(PIXELS_PER_CHUNK);
+
+    for samples in pixels.chunks_mut(N * PIXELS_PER_CHUNK) {
+        chunk.clear();
+        chunk.extend(samples.chunks_exact(N).map(|pixel| <[T; N]>::try_from(pixel).unwrap()));
+        transform.transform_in_place(&mut chunk);
+        samples.copy_from_slice(chunk.as_flattened());
+    }
+}
+
+// Converts `pixels` from `source` to sRGB in place, false when lcms cannot build the transform.
+// Alpha is left in place, lcms only writes the colour channels
+fn convert_pixels<T: Copy, const N: usize>(pixels: &mut [T], source: &Profile, format: PixelFormat) -> bool {
+    Transform::<[T; N], [T; N]>::new(source, format, &Profile::new_srgb(), format, Intent::Perceptual)
+        .map(|transform| convert(pixels, &transform))
+        .is_ok()
+}
+
+// The profile to convert from, `None` when there is nothing to convert
+fn source_profile(profile: &IccProfile) -> Option<Profile> {
+    if profile.color_space != Some("rgb") || profile.is_srgb() {
+        return None;
+    }
+
+    Profile::new_icc(&profile.data).ok()
+}
+
+/// Converts an 8-bit preview from `profile` to sRGB, which is what the webview shows it as.
+/// Also returns whether it was converted: grey previews, sRGB profiles and profiles lcms
+/// cannot read or build a transform for are left as they are.
+pub fn convert_to_srgb(preview: DynImage<Vec<u8>>, profile: &IccProfile) -> (DynImage<Vec<u8>>, bool) {
+    let Some(source) = source_profile(profile) else {
+        return (preview, false);
+    };
+    let (width, height) = (preview.width(), preview.height());
+
+    match preview {
+        DynImage::Rgb(image) => {
+            let mut pixels = image.take_buffer();
+            let converted = convert_pixels::<u8, 3>(&mut pixels, &source, PixelFormat::RGB_8);
+
+            (DynImage::Rgb(Image::build(width, height).buf(pixels)), converted)
+        }
+        DynImage::Rgba(image) => {
+            let mut pixels = image.take_buffer();
+            let converted = convert_pixels::<u8, 4>(&mut pixels, &source, PixelFormat::RGBA_8);
+
+            (DynImage::Rgba(Image::build(width, height).buf(pixels)), converted)
+        }
+        preview => (preview, false),
+    }
+}
+
+/// Converts an exported image from `profile` to sRGB in place, keeping its bit depth, since
+/// exports are written without a profile. Skips the same images as [`convert_to_srgb`], as
+/// well as floating-point ones.
+pub fn convert_image_to_srgb(image: &mut DynamicImage, profile: &IccProfile) {
+    let Some(source) = source_profile(profile) else {
+        return;
+    };
+
+    match image {
+        DynamicImage::ImageRgb8(buffer) => convert_pixels::<u8, 3>(buffer, &source, PixelFormat::RGB_8),
+        DynamicImage::ImageRgba8(buffer) => convert_pixels::<u8, 4>(buffer, &source, PixelFormat::RGBA_8),
+        DynamicImage::ImageRgb16(buffer) => convert_pixels::<u16, 3>(buffer, &source, PixelFormat::RGB_16),
+        DynamicImage::ImageRgba16(buffer) => convert_pixels::<u16, 4>(buffer, &source, PixelFormat::RGBA_16),
+        _ => false,
+    };
+}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tauri::State;
+
+pub type JobId = u64;
+
+/// Shared flag a running job polls to find out whether it should stop.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Every running import, kept in Tauri managed state so `cancel_job` can reach it.
+#[derive(Debug, Default)]
+pub struct JobRegistry {
+    next_id: AtomicU64,
+    jobs: Mutex<HashMap<JobId, CancellationToken>>,
+}
+
+impl JobRegistry {
+    pub fn register(&self) -> (JobId, CancellationToken) {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let token = CancellationToken::default();
+
+        self.jobs.lock().unwrap().insert(id, token.clone());
+
+        (id, token)
+    }
+
+    pub fn cancel(&self, id: JobId) -> bool {
+        match self.jobs.lock().unwrap().get(&id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn finish(&self, id: JobId) {
+        self.jobs.lock().unwrap().remove(&id);
+    }
+
+    pub fn running(&self) -> Vec<JobId> {
+        let mut ids: Vec<JobId> = self.jobs.lock().unwrap().keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+}
+
+#[tauri::command]
+pub fn cancel_job(id: JobId, registry: State<'_, JobRegistry>) -> Result<(), String> {
+    if registry.cancel(id) {
+        Ok(())
+    } else {
+        Err(format!("No running job with id {}", id))
+    }
+}
+
+#[tauri::command]
+pub fn list_jobs(registry: State<'_, JobRegistry>) -> Vec<JobId> {
+    registry.running()
+}
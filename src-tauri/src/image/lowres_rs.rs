@@ -1,128 +1,458 @@
-use std::path::PathBuf;
+use std::fs::File;
+use std::io::BufReader;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-use fimg::scale::Lanczos3;
 use fimg::{DynImage, Image};
-use image::ImageReader;
+use image::metadata::Orientation;
+use image::{ColorType, DynamicImage, ImageDecoder, ImageReader};
+use rayon::prelude::*;
+use serde::Deserialize;
 use serde_json::json;
 use sha2::{Sha256, Digest};
-use tauri::{ipc::Channel, AppHandle};
+use tauri::{ipc::Channel, AppHandle, State};
 use tokio::time::Instant;
 use rexiv2::Metadata;
 
 use crate::global::IMAGE_CACHE_DIR;
+use crate::image::cache::{self, CachePins, WritingGuard};
+use crate::image::cache_index::{CacheIndex, SourceFingerprint};
+use crate::image::jobs::{CancellationToken, JobRegistry};
+use crate::image::pool::{self, HashClaims, MemoryBudget, MemoryReservation};
+use crate::image::error::{display_filename, ImportEntry, ImportError, ImportFailure};
+use crate::image::formats::{self, OutputFormat};
+use crate::image::icc::{self, IccProfile};
+use crate::image::metadata;
+use crate::image::pages::{self, PageSequence};
+use crate::image::raw;
+use crate::image::resample::ResampleFilter;
+use crate::image::resize::{self, ResizeBackend};
+use crate::image::resolution::{self, Resolution, ResolutionSource, ResolutionUnit};
+use crate::image::svg;
+use crate::image::tiff_writer;
+use crate::image::tiles;
 use crate::utilities::file_utils;
 
-const MAXIMUM_DIMENSION: u32 = 1024;
+const DEFAULT_PREVIEW_SIZE: u32 = 1024;
+const STEPS_PER_FILE: usize = 5;
+const DEFAULT_MEMORY_BUDGET_MB: u64 = 4096;
 
-fn prepare_directories(image_cache_dir: &PathBuf) -> (PathBuf, PathBuf) {
+struct CacheDirs {
+    lowres: PathBuf,
+    highres: PathBuf,
+    tiles: PathBuf,
+}
+
+fn prepare_directories(image_cache_dir: &Path) -> CacheDirs {
     let lowres_dir = image_cache_dir.join("lowres");
     let highres_dir = image_cache_dir.join("highres");
+    let tiles_dir = image_cache_dir.join("tiles");
 
-    file_utils::create_dir_if_not_exists(&image_cache_dir);
+    file_utils::create_dir_if_not_exists(image_cache_dir);
     file_utils::create_dir_if_not_exists(&lowres_dir);
     file_utils::create_dir_if_not_exists(&highres_dir);
+    file_utils::create_dir_if_not_exists(&tiles_dir);
 
-    (lowres_dir, highres_dir)
+    CacheDirs {
+        lowres: lowres_dir,
+        highres: highres_dir,
+        tiles: tiles_dir,
+    }
 }
 
-fn calculate_new_dimensions(image: &Image<Vec<u8>, 3>) -> Option<(u32, u32)> {
-    let width = image.width() as f32;
-    let height = image.height() as f32;
+pub(crate) fn calculate_new_dimensions(width: u32, height: u32, maximum_dimension: u32) -> Option<(u32, u32)> {
+    let width = width as f32;
+    let height = height as f32;
+    let maximum_dimension = maximum_dimension.max(1);
 
     let longest_edge = width.max(height);
     
-    // If image is smaller than or equal to MAXIMUM_DIMENSION, return None
-    if longest_edge <= MAXIMUM_DIMENSION as f32 {
+    // If image is smaller than or equal to maximum_dimension, return None
+    if longest_edge <= maximum_dimension as f32 {
         return None;
     }
 
-    let scale_factor = longest_edge / MAXIMUM_DIMENSION as f32;
+    let scale_factor = longest_edge / maximum_dimension as f32;
 
-    let new_width = (width / scale_factor).round() as u32;
-    let new_height = (height / scale_factor).round() as u32;
+    // A very thin image would round its short side down to nothing
+    let new_width = ((width / scale_factor).round() as u32).max(1);
+    let new_height = ((height / scale_factor).round() as u32).max(1);
 
     Some((new_width, new_height))
 }
 
-fn get_image_hash(image: &Image<Vec<u8>, 3>) -> String {
+pub(crate) fn get_image_hash(image: &DynamicImage) -> String {
 	let mut hasher = Sha256::new();
-	hasher.update(image.bytes());
+	// 8-bit RGB keeps hashing the bare pixels so images cached before other colour types were kept still match
+	if image.color() != ColorType::Rgb8 {
+		hasher.update(format!("{:?}:{}x{}", image.color(), image.width(), image.height()));
+	}
+	hasher.update(image.as_bytes());
 	let hash = hasher.finalize();
 	hex::encode(hash)
 }
 
-fn get_lowres_image(image: &mut Image<Vec<u8>, 3>, image_path: &PathBuf, new_width: u32, new_height: u32) -> Image<Box<[u8]>, 3> {
-    let output;
-    if image_path.exists() {
-        output = DynImage::open(image_path).to_rgb();
-    } else {
-        // Create a new image with the correct dimensions and pixel data
-        let scaled = image.scale::<Lanczos3>(new_width, new_height);
-        
-        // Save the RGB image directly
-        scaled.save(image_path);
-        
-        output = scaled;
+/// [`get_image_hash`] extended by what the high-res copy stores next to the pixels: the ICC
+/// profile and a resolution read from the file. The same pixels with another profile or DPI
+/// get copies of their own, files with neither keep the hash of their pixels alone.
+fn get_source_hash(image: &DynamicImage, icc_profile: Option<&IccProfile>, resolution: &Resolution) -> String {
+    let pixel_hash = get_image_hash(image);
+    if icc_profile.is_none() && !resolution.measured {
+        return pixel_hash;
     }
 
-    output
+    let mut hasher = Sha256::new();
+    hasher.update(pixel_hash);
+    if let Some(icc_profile) = icc_profile {
+        hasher.update(":icc:");
+        hasher.update(&icc_profile.data);
+    }
+    if resolution.measured {
+        hasher.update(format!(":dpi:{}x{}:{:?}", resolution.x_dpi, resolution.y_dpi, resolution.unit));
+    }
+
+    hex::encode(hasher.finalize())
 }
 
-fn get_dpi(image_path: &PathBuf) -> Option<u32> {
-    if let Ok(metadata) = Metadata::new_from_path(image_path) {
-        let x_resolution = metadata.get_tag_string("Exif.Image.XResolution");
-        let y_resolution = metadata.get_tag_string("Exif.Image.YResolution");
-        
-        if let (Ok(x_res), Ok(y_res)) = (x_resolution, y_resolution) {
-            // Parse X resolution
-            let x_resolution_value = x_res.split('/')
-                .map(|x| x.parse::<f64>().unwrap_or(72.0))
-                .collect::<Vec<f64>>();
-            let x_dpi = if x_resolution_value.len() >= 2 && x_resolution_value[1] != 0.0 {
-                x_resolution_value[0] / x_resolution_value[1]
-            } else {
-                72.0
+/// Hash for a file with several pages or frames, covering every page and its timing so files
+/// that only share their first page do not share cached pages. Single page files keep the hash
+/// of their pixels. Each page after the first is decoded once: it is hashed and cached under a
+/// temporary name, and the copies are moved under the hash once every page is in. The hash
+/// comes back marked as being written, so nothing evicts the copies before they are recorded.
+/// Each page reserves its memory before it is decoded, next to `held` for the first page.
+fn save_later_pages(
+    file: &str,
+    first_page_hash: &str,
+    orientation: Orientation,
+    resolution: &Resolution,
+    icc_profile: Option<&IccProfile>,
+    held: &MemoryReservation,
+    pipeline: &Pipeline,
+) -> Result<(String, PageSequence, Vec<SavedPage>, WritingGuard), ImportError> {
+    let dirs = &pipeline.dirs;
+    let cancel = pipeline.cancel;
+    let temp_name = format!("{}{}", first_page_hash, file_utils::temp_suffix());
+    // The temporary copies are counted as the first page's by eviction
+    let first_page_writing = pipeline.pins.writing(first_page_hash);
+
+    let mut hasher = Sha256::new();
+    hasher.update(first_page_hash);
+    let mut saved = Vec::new();
+    let mut page_reservation = None;
+
+    let reserve = |width, height, bytes_per_pixel| {
+        // The previous page is done by now, its share goes back before the next one waits
+        page_reservation = None;
+        let estimate = pool::estimate_decoded_memory(width, height, bytes_per_pixel);
+        page_reservation = Some(pipeline.budget.acquire_more(estimate, held, cancel).ok_or(ImportError::Cancelled)?);
+        Ok(())
+    };
+
+    let walked = pages::for_each_page(Path::new(file), reserve, |index, page| {
+        if cancel.is_cancelled() {
+            return Err(ImportError::Cancelled);
+        }
+
+        let mut image = page.image;
+        image.apply_orientation(orientation);
+        hasher.update(format!(":{}:{:?}", get_image_hash(&image), page.delay_ms));
+        saved.push(save_page(&image, index, page.delay_ms, resolution, icc_profile, &temp_name, pipeline)?);
+        Ok(())
+    });
+
+    // The last page has been cached, nothing of it is held any more
+    drop(page_reservation);
+
+    let moves = [&dirs.highres, &dirs.lowres, &dirs.tiles].map(|dir| (dir.join(&temp_name), dir));
+    let sequence = match walked {
+        Ok(sequence) => sequence,
+        Err(e) => {
+            for (temp_dir, _) in &moves {
+                let _ = std::fs::remove_dir_all(temp_dir);
+            }
+            return Err(e);
+        }
+    };
+
+    if sequence.page_count == 1 {
+        return Ok((first_page_hash.to_string(), sequence, saved, first_page_writing));
+    }
+
+    hasher.update(format!(":{:?}", sequence.first_delay_ms));
+    let hash = hex::encode(hasher.finalize());
+    let writing = pipeline.pins.writing(&hash);
+
+    for (temp_dir, dir) in &moves {
+        if temp_dir.exists() {
+            file_utils::move_into_place(temp_dir, &dir.join(&hash)).map_err(ImportError::Save)?;
+        }
+    }
+
+    // Manifests still name the directory the pyramids were generated in
+    if pipeline.options.generate_tiles {
+        for page in &saved {
+            let tiles_dir = CachePaths::for_page(dirs, &hash, page.index, pipeline.options).tiles;
+            tiles::relocate_manifest(&tiles_dir).map_err(ImportError::Tiles)?;
+        }
+    }
+
+    Ok((hash, sequence, saved, writing))
+}
+
+/// 8-bit copy of `image` for previews and tiles, keeping alpha when there is any.
+pub fn to_preview_image(image: &DynamicImage) -> DynImage<Vec<u8>> {
+    let (width, height) = (image.width(), image.height());
+    let color = image.color();
+
+    match (color.channel_count(), color.has_alpha()) {
+        (1, _) => DynImage::Y(Image::build(width, height).buf(image.to_luma8().into_raw())),
+        (2, _) => DynImage::Ya(Image::build(width, height).buf(image.to_luma_alpha8().into_raw())),
+        (_, true) => DynImage::Rgba(Image::build(width, height).buf(image.to_rgba8().into_raw())),
+        (_, false) => DynImage::Rgb(Image::build(width, height).buf(image.to_rgb8().into_raw())),
+    }
+}
+
+/// [`to_preview_image`] converted from the source's ICC profile to sRGB, and whether it was.
+pub fn to_srgb_preview(image: &DynamicImage, icc_profile: Option<&IccProfile>) -> (DynImage<Vec<u8>>, bool) {
+    let preview = to_preview_image(image);
+
+    match icc_profile {
+        Some(icc_profile) => icc::convert_to_srgb(preview, icc_profile),
+        None => (preview, false),
+    }
+}
+
+/// Where the cached copies of one page go. The first page is the image itself, later
+/// pages of a document or animation are kept under `<hash>/page-N` in each directory.
+struct CachePaths {
+    highres: PathBuf,
+    lowres: PathBuf,
+    /// Directory holding one `<size>.<ext>` file per rendition
+    renditions: PathBuf,
+    tiles: PathBuf,
+}
+
+impl CachePaths {
+    fn for_page(dirs: &CacheDirs, hash: &str, page: usize, options: &ImportOptions) -> Self {
+        let lowres_name = format!("{}.{}", preview_key(options), options.preview_format.extension());
+
+        if page == 0 {
+            return Self {
+                highres: dirs.highres.join(format!("{}.tiff", hash)),
+                lowres: dirs.lowres.join(format!("{}{}", hash, lowres_name)),
+                renditions: dirs.lowres.join(hash),
+                tiles: dirs.tiles.join(hash),
             };
+        }
 
-            // Parse Y resolution
-            let y_resolution_value = y_res.split('/')
-                .map(|x| x.parse::<f64>().unwrap_or(72.0))
-                .collect::<Vec<f64>>();
-            let y_dpi = if y_resolution_value.len() >= 2 && y_resolution_value[1] != 0.0 {
-                y_resolution_value[0] / y_resolution_value[1]
-            } else {
-                72.0
+        let page_name = pages::page_name(page);
+
+        Self {
+            highres: dirs.highres.join(hash).join(format!("{}.tiff", page_name)),
+            lowres: dirs.lowres.join(hash).join(format!("{}{}", page_name, lowres_name)),
+            renditions: dirs.lowres.join(hash).join(&page_name),
+            tiles: dirs.tiles.join(hash).join(page_name),
+        }
+    }
+
+    fn rendition(&self, size: u32, options: &ImportOptions) -> PathBuf {
+        let filter_key = match options.resample_filter {
+            ResampleFilter::Lanczos3 => String::new(),
+            filter => format!("-{}", filter.name()),
+        };
+
+        self.renditions.join(format!(
+            "{}{}{}{}.{}",
+            size,
+            filter_key,
+            backend_key(options),
+            encoder_key(options),
+            options.preview_format.extension()
+        ))
+    }
+}
+
+// Previews made with other settings than the defaults get them in their name, e.g.
+// `<hash>.2048-nearest.png` or `<hash>.1024-lanczos3-q60.jpg`, so each configuration keeps its own copy
+fn preview_key(options: &ImportOptions) -> String {
+    let default_size = options.preview_size == DEFAULT_PREVIEW_SIZE && options.resample_filter == ResampleFilter::default();
+    let encoder_key = encoder_key(options);
+    if default_size && options.resize_backend == ResizeBackend::default() && encoder_key.is_empty() {
+        return String::new();
+    }
+
+    format!(".{}-{}{}{}", options.preview_size, options.resample_filter.name(), backend_key(options), encoder_key)
+}
+
+fn encoder_key(options: &ImportOptions) -> String {
+    match options.preview_format.encoder_key(options.preview_quality) {
+        key if key.is_empty() => key,
+        key => format!("-{}", key),
+    }
+}
+
+fn backend_key(options: &ImportOptions) -> String {
+    match options.resize_backend {
+        ResizeBackend::Fimg => String::new(),
+        backend => format!("-{}", backend.name()),
+    }
+}
+
+fn create_lowres_image(
+    image: &DynImage<Vec<u8>>,
+    image_path: &Path,
+    (new_width, new_height): (u32, u32),
+    options: &ImportOptions,
+) -> Result<(), ImportError> {
+    if image_path.exists() {
+        return Ok(());
+    }
+
+    let format = options.preview_format;
+    let quality = options.preview_quality;
+
+    // The high-res copy is a TIFF the webview cannot show, so small images still get a preview at full size
+    if image.width() == new_width && image.height() == new_height {
+        return file_utils::write_atomically(image_path, |path| formats::write_preview(image, format, quality, path))
+            .map_err(ImportError::Save);
+    }
+
+    // Create a new image with the correct dimensions and pixel data
+    let scaled = resize::resize(image, new_width, new_height, options.resample_filter, options.resize_backend)
+        .map_err(ImportError::Resize)?;
+
+    file_utils::write_atomically(image_path, |path| formats::write_preview(&scaled, format, quality, path)).map_err(ImportError::Save)
+}
+
+/// Size of one rendition [`create_renditions`] wrote.
+struct Rendition {
+    size: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Rendition {
+    fn entry(&self, paths: &CachePaths, options: &ImportOptions) -> serde_json::Value {
+        json!({
+            "size": self.size,
+            "width": self.width,
+            "height": self.height,
+            "path": paths.rendition(self.size, options).to_str().unwrap()
+        })
+    }
+}
+
+fn rendition_entries(renditions: &[Rendition], paths: &CachePaths, options: &ImportOptions) -> Vec<serde_json::Value> {
+    renditions.iter().map(|rendition| rendition.entry(paths, options)).collect()
+}
+
+/// Writes one copy per size in `options.renditions`, largest first, each scaled down from the
+/// one before it so only the largest is scaled from the full image. Sizes above the image's
+/// own get a copy at full size, so every requested size can be looked up.
+fn create_renditions(
+    image: &DynImage<Vec<u8>>,
+    paths: &CachePaths,
+    options: &ImportOptions,
+) -> Result<Vec<Rendition>, ImportError> {
+    let mut sizes = options.renditions.clone();
+    sizes.sort_unstable_by(|a, b| b.cmp(a));
+    sizes.dedup();
+
+    if sizes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    file_utils::create_dir_if_not_exists(&paths.renditions);
+
+    let (width, height) = (image.width(), image.height());
+    let mut previous: Option<DynImage<Box<[u8]>>> = None;
+    let mut renditions = Vec::with_capacity(sizes.len());
+
+    for size in sizes {
+        let (rendition_width, rendition_height) = calculate_new_dimensions(width, height, size).unwrap_or((width, height));
+        let rendition_path = paths.rendition(size, options);
+
+        if !rendition_path.exists() {
+            let source_dimensions = previous.as_ref().map_or((width, height), |previous| (previous.width(), previous.height()));
+
+            let write = |image: &DynImage<&[u8]>| {
+                file_utils::write_atomically(&rendition_path, |path| {
+                    formats::write_preview(image, options.preview_format, options.preview_quality, path)
+                })
             };
 
-            // Use the average of X and Y DPI, or just X if they're the same
-            Some(if (x_dpi - y_dpi).abs() < 0.1 {
-                x_dpi as u32
+            let written = if source_dimensions == (rendition_width, rendition_height) {
+                match &previous {
+                    Some(previous) => write(&previous.as_ref()),
+                    None => write(&image.as_ref()),
+                }
             } else {
-                ((x_dpi + y_dpi) / 2.0) as u32
-            })
-        } else {
-            Some(72)
+                let scaled = match &previous {
+                    Some(previous) => resize::resize(previous, rendition_width, rendition_height, options.resample_filter, options.resize_backend),
+                    None => resize::resize(image, rendition_width, rendition_height, options.resample_filter, options.resize_backend),
+                }
+                .map_err(ImportError::Resize)?;
+                let written = write(&scaled.as_ref());
+                previous = Some(scaled);
+                written
+            };
+            written.map_err(ImportError::Save)?;
         }
-    } else {
-        Some(72) // Default to 72 DPI if metadata can't be read
+
+        renditions.push(Rendition {
+            size,
+            width: rendition_width,
+            height: rendition_height,
+        });
     }
+
+    Ok(renditions)
 }
 
-fn get_image_data(image_path: PathBuf) -> String {
+// An embedded preview can stand in for the low-res copy if it is at least as large and shows the same framing
+fn fits_lowres(preview: &DynamicImage, (highres_width, highres_height): (u32, u32), (lowres_width, lowres_height): (u32, u32)) -> bool {
+    let preview_aspect = preview.width() as f32 / preview.height() as f32;
+    let highres_aspect = highres_width as f32 / highres_height as f32;
+
+    preview.width() >= lowres_width
+        && preview.height() >= lowres_height
+        && (preview_aspect - highres_aspect).abs() / highres_aspect < 0.01
+}
+
+fn color_info(color: ColorType) -> serde_json::Value {
+    json!({
+        "type": format!("{:?}", color),
+        "channels": color.channel_count(),
+        "bits_per_channel": color.bits_per_pixel() / color.channel_count() as u16,
+        "has_alpha": color.has_alpha()
+    })
+}
+
+/// The EXIF orientation of the source file, `NoTransforms` when it has none.
+pub(crate) fn get_orientation(image_path: &str) -> Orientation {
+    Metadata::new_from_path(image_path)
+        .ok()
+        // gexiv2 numbers its orientations exactly like the EXIF tag
+        .and_then(|metadata| Orientation::from_exif(metadata.get_orientation() as u8))
+        .unwrap_or(Orientation::NoTransforms)
+}
+
+fn get_image_data(image_path: &Path) -> Result<String, ImportError> {
     // Get file size
-    let metadata = std::fs::metadata(&image_path).expect("Unable to read metadata");
+    let metadata = std::fs::metadata(image_path).map_err(ImportError::Metadata)?;
     let file_size = metadata.len();
     let size_in_kb = file_size as f64 / 1024.0;
     let size_in_mb = size_in_kb / 1024.0;
     let size_in_gb = size_in_mb / 1024.0;
 
-    if size_in_gb >= 1.0 {
+    Ok(if size_in_gb >= 1.0 {
         format!("{:.2} GB", size_in_gb)
     } else if size_in_mb >= 1.0 {
         format!("{:.2} MB", size_in_mb)
     } else {
         format!("{:.2} KB", size_in_kb)
-    }
+    })
 }
 
 fn calculate_progress(current_step: usize, total_steps: usize) -> f32 {
@@ -138,152 +468,843 @@ fn calculate_progress(current_step: usize, total_steps: usize) -> f32 {
     }
 }
 
-#[tauri::command]
-pub async fn load_and_resize_images(app_handle: AppHandle, channel: Channel) -> Result<Vec<serde_json::Value>, ()> {
-    let image_cache_dir = IMAGE_CACHE_DIR.lock().unwrap().clone();
-    let (lowres_dir, highres_dir) = prepare_directories(&image_cache_dir);
+fn progress_event(current_step: usize, total_steps: usize, step: &str, file: serde_json::Value) -> serde_json::Value {
+    json!({
+        "event": "progress",
+        "data": {
+            "percentage": calculate_progress(current_step, total_steps),
+            "step": step,
+            "file": file
+        }
+    })
+}
 
-    let selected_files = file_utils::open_image_dialog(app_handle);
-    let start_time = Instant::now();
+fn file_progress(index: usize, file: &str, file_step: usize) -> serde_json::Value {
+    json!({
+        "index": index,
+        "filename": display_filename(file),
+        "percentage": calculate_progress(file_step, STEPS_PER_FILE)
+    })
+}
+
+pub(crate) fn error_event(failure: &ImportFailure) -> serde_json::Value {
+    json!({
+        "event": "error",
+        "data": failure
+    })
+}
+
+pub(crate) fn send_event(channel: &Channel, event: serde_json::Value) {
+    let _ = channel.send(tauri::ipc::InvokeResponseBody::Json(event.to_string()));
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ImportOptions {
+    pub generate_tiles: bool,
+    pub tile_size: u32,
+    /// Number of files processed at once, defaults to the number of CPU cores
+    pub workers: Option<usize>,
+    /// Upper bound for decoded pixel data held by all workers together
+    pub memory_budget_mb: u64,
+    /// Resolution SVG documents are rasterized at
+    pub svg_dpi: f32,
+    /// Longest edge in pixels to rasterize SVG documents at, overrides `svg_dpi`
+    pub svg_target_size: Option<u32>,
+    /// Longest edge of the cached low-res copy in pixels
+    pub preview_size: u32,
+    /// Longest edges of the extra low-res copies made for `srcset` style selection
+    pub renditions: Vec<u32>,
+    /// Filter the low-res copy is scaled down with
+    pub resample_filter: ResampleFilter,
+    /// Library previews, renditions and tiles are scaled with
+    pub resize_backend: ResizeBackend,
+    /// Format of the cached low-res copy, PNG unless the webview can show something smaller
+    pub preview_format: OutputFormat,
+    /// Quality (1-100) of the low-res copy for lossy preview formats
+    pub preview_quality: u8,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            generate_tiles: true,
+            tile_size: tiles::TILE_SIZE,
+            workers: None,
+            memory_budget_mb: DEFAULT_MEMORY_BUDGET_MB,
+            svg_dpi: svg::SVG_BASE_DPI,
+            svg_target_size: None,
+            preview_size: DEFAULT_PREVIEW_SIZE,
+            renditions: Vec::new(),
+            resample_filter: ResampleFilter::default(),
+            resize_backend: ResizeBackend::default(),
+            preview_format: OutputFormat::Png,
+            preview_quality: formats::DEFAULT_QUALITY,
+        }
+    }
+}
+
+impl ImportOptions {
+    /// Brings values the pipeline cannot work with into range, options come from the frontend as they are.
+    pub fn validated(mut self) -> Self {
+        self.tile_size = self.tile_size.clamp(tiles::MIN_TILE_SIZE, tiles::MAX_TILE_SIZE);
+        self.preview_size = self.preview_size.max(1);
+        self
+    }
+
+    /// `memory_budget_mb` in bytes, a budget too large to count in bytes is as good as none.
+    pub fn memory_budget_bytes(&self) -> u64 {
+        self.memory_budget_mb.saturating_mul(1024 * 1024)
+    }
+}
+
+/// Everything the workers of one batch share.
+struct Pipeline<'a> {
+    options: &'a ImportOptions,
+    dirs: CacheDirs,
+    cancel: &'a CancellationToken,
+    budget: MemoryBudget,
+    claims: HashClaims,
+    index: &'a CacheIndex,
+    pins: &'a CachePins,
+}
+
+fn open_reader(file: &str) -> Result<ImageReader<BufReader<File>>, ImportError> {
+    ImageReader::open(file)
+        .map_err(ImportError::Open)?
+        .with_guessed_format()
+        .map_err(ImportError::UnknownFormat)
+}
+
+/// A decoded source file and the budget it holds.
+pub(crate) struct Decoded<'a> {
+    pub(crate) image: DynamicImage,
+    /// Camera-rendered preview embedded in a RAW file, used for the low-res copy
+    pub(crate) embedded_preview: Option<DynamicImage>,
+    /// Still to be applied to `image`, formats that store their own orientation come out upright
+    pub(crate) orientation: Orientation,
+    /// As stored in the source file, before `orientation` is applied
+    pub(crate) resolution: Resolution,
+    pub(crate) icc_profile: Option<IccProfile>,
+    /// Has to be held until the file is done, the copies made from `image` count against it too
+    reservation: MemoryReservation<'a>,
+}
+
+/// Decodes `file` once its estimated memory fits the budget.
+pub(crate) fn decode_source<'a>(
+    file: &str,
+    options: &ImportOptions,
+    budget: &'a MemoryBudget,
+    cancel: &CancellationToken,
+) -> Result<Decoded<'a>, ImportError> {
+    // A RAW file is only sized by decoding it, so it waits for what a file of its size could need
+    if raw::is_raw(Path::new(file)) {
+        let file_size = std::fs::metadata(file).map_err(ImportError::Open)?.len();
+        let mut reservation = budget
+            .acquire(pool::estimate_raw_file_memory(file_size), cancel)
+            .ok_or(ImportError::Cancelled)?;
+
+        // Read once for both the sensor data and the embedded preview
+        let data = std::fs::read(file).map_err(ImportError::Open)?;
+        let sensor = raw::decode_raw(&data)?;
+        let (width, height) = raw::developed_dimensions(&sensor);
+        reservation.shrink_to(pool::estimate_raw_memory(file_size, raw::sample_count(&sensor), width, height));
+
+        return Ok(Decoded {
+            image: raw::develop(&sensor)?,
+            embedded_preview: raw::extract_preview(&data),
+            orientation: get_orientation(file),
+            resolution: resolution::read_resolution(Path::new(file)),
+            // Developed into sRGB, whatever the camera embedded
+            icc_profile: None,
+            reservation,
+        });
+    }
+
+    // SVG has no pixels to decode, it is rendered at the size the options ask for
+    if svg::is_svg(Path::new(file)) {
+        let tree = svg::load_svg(Path::new(file))?;
+        let (width, height) = svg::raster_dimensions(&tree, options.svg_dpi, options.svg_target_size);
+
+        let reservation = budget
+            .acquire(pool::estimate_raster_memory(width, height), cancel)
+            .ok_or(ImportError::Cancelled)?;
+
+        // SVG user units are CSS pixels, so the density is however far the raster was scaled from those
+        let dpi = (svg::SVG_BASE_DPI * width as f32 / tree.size().width()) as f64;
+
+        return Ok(Decoded {
+            image: svg::rasterize(&tree, width, height)?,
+            embedded_preview: None,
+            orientation: Orientation::NoTransforms,
+            resolution: Resolution::new(dpi, dpi, ResolutionUnit::Inch, ResolutionSource::Svg),
+            icc_profile: None,
+            reservation,
+        });
+    }
+
+    if formats::is_heif(Path::new(file)) {
+        let source = formats::HeifSource::open(Path::new(file))?;
+        let (width, height) = source.dimensions();
+
+        let reservation = budget
+            .acquire(pool::estimate_decoded_memory(width, height, source.bytes_per_pixel()), cancel)
+            .ok_or(ImportError::Cancelled)?;
+
+        return Ok(Decoded {
+            image: source.decode()?,
+            embedded_preview: None,
+            orientation: Orientation::NoTransforms,
+            resolution: resolution::read_resolution(Path::new(file)),
+            icc_profile: source.icc_profile().map(IccProfile::new),
+            reservation,
+        });
+    }
+
+    if formats::is_jxl(Path::new(file)) {
+        let mut decoder = formats::open_jxl(Path::new(file))?;
+        let icc_profile = decoder.icc_profile().ok().flatten().map(IccProfile::new);
+
+        let reservation = budget
+            .acquire(pool::estimate_memory(&decoder), cancel)
+            .ok_or(ImportError::Cancelled)?;
+
+        return Ok(Decoded {
+            image: DynamicImage::from_decoder(decoder).map_err(ImportError::Decode)?,
+            embedded_preview: None,
+            orientation: Orientation::NoTransforms,
+            resolution: resolution::read_resolution(Path::new(file)),
+            icc_profile,
+            reservation,
+        });
+    }
+
+    // Only the header is read here, so the file can wait for its share of the budget before decoding
+    let mut decoder = open_reader(file)?
+        .into_decoder()
+        .map_err(ImportError::Decode)?;
+    let icc_profile = decoder.icc_profile().ok().flatten().map(IccProfile::new);
+    let reservation = budget
+        .acquire(pool::estimate_memory(&decoder), cancel)
+        .ok_or(ImportError::Cancelled)?;
+    drop(decoder);
+
+    let mut reader = open_reader(file)?;
+    reader.no_limits();
+    let image = reader.decode().map_err(ImportError::Decode)?;
+
+    Ok(Decoded {
+        image,
+        embedded_preview: None,
+        orientation: get_orientation(file),
+        resolution: resolution::read_resolution(Path::new(file)),
+        icc_profile,
+        reservation,
+    })
+}
+
+/// Runs the five pipeline steps for a single file, calling `on_step` as each one starts.
+/// `on_step` fails with `ImportError::Cancelled` once the job has been cancelled.
+fn process_image(
+    file: &str,
+    pipeline: &Pipeline,
+    on_step: &mut dyn FnMut(&str) -> Result<(), ImportError>,
+) -> Result<serde_json::Value, ImportError> {
+    let options = pipeline.options;
+    let dirs = &pipeline.dirs;
+    let cancel = pipeline.cancel;
+
+    // Step 1: Opening and decoding image
+    on_step("Opening image")?;
+
+    // A file seen before can be answered from the cache without decoding it
+    let mut fingerprint = SourceFingerprint::read(file).map_err(ImportError::Open)?;
+    if let Some(output) = lookup_cached(file, &mut fingerprint, pipeline) {
+        return Ok(finish_output(file, output, &mut fingerprint, pipeline));
+    }
+
+    let decoded = decode_source(file, options, &pipeline.budget, cancel)?;
+    let mut highres_image = decoded.image;
+    let mut embedded_preview = decoded.embedded_preview;
+    let orientation = decoded.orientation;
+    let resolution = decoded.resolution.oriented(orientation);
+    let icc_profile = decoded.icc_profile;
+
+    // Both cached copies are stored upright, so the hash is taken after rotating
+    let raw_dimensions = (highres_image.width(), highres_image.height());
+    highres_image.apply_orientation(orientation);
+    if let Some(preview) = embedded_preview.as_mut() {
+        preview.apply_orientation(orientation);
+    }
+
+    // Step 2: Generating hash over the pixels in their original colour type, with their profile and resolution
+    on_step("Processing image")?;
+
+    let first_page_hash = get_source_hash(&highres_image, icc_profile.as_ref(), &resolution);
+
+    // Held until the entry is recorded, so the same file listed twice in a batch is only
+    // cached once and the second copy finds it in the cache. Files that share their first
+    // page wait for each other before any later page is written, whatever they hash to
+    let _claim = pipeline.claims.claim(&first_page_hash, cancel).ok_or(ImportError::Cancelled)?;
+    if let Some(output) = lookup_cached(file, &mut fingerprint, pipeline) {
+        return Ok(finish_output(file, output, &mut fingerprint, pipeline));
+    }
+    // A paged file is only known by its full hash, which takes walking its pages
+    if !pages::is_paged(Path::new(file)) {
+        if let Some(output) = cached_output(&first_page_hash, pipeline) {
+            return Ok(finish_output(file, output, &mut fingerprint, pipeline));
+        }
+    }
+
+    // The first page is still held while the others are walked, each page reserves its own
+    // memory next to the first page's
+    let (hash, sequence, later_pages, _writing) = save_later_pages(
+        file,
+        &first_page_hash,
+        orientation,
+        &resolution,
+        icc_profile.as_ref(),
+        &decoded.reservation,
+        pipeline,
+    )?;
+
+    if let Some(output) = cached_output(&hash, pipeline) {
+        return Ok(finish_output(file, output, &mut fingerprint, pipeline));
+    }
+
+    // Step 3: Creating low-res version
+    on_step("Creating low-res version")?;
+
+    let paths = CachePaths::for_page(dirs, &hash, 0, options);
+
+    let highres_width = highres_image.width();
+    let highres_height = highres_image.height();
     
-    let total_steps = selected_files.len() * 5; // 5 steps per file
-    let mut current_step = 0;
-
-	let mut results = Vec::new();
-    // Loop selected_files and open image
-    for file in &selected_files {
-        // Step 1: Opening and decoding image
-        current_step += 1;
-        let _ = channel.send(tauri::ipc::InvokeResponseBody::Json(serde_json::json!({
-            "event": "progress",
-            "data": {
-                "percentage": calculate_progress(current_step, total_steps),
-                "step": "Opening image"
-            }
-        }).to_string()));
+    // Calculate dimensions and decide if we need a lowres version
+    let lowres_dimensions = calculate_new_dimensions(highres_width, highres_height, options.preview_size)
+        .unwrap_or((highres_width, highres_height));
 
-        let mut reader = ImageReader::open(file)
-            .expect("Failed to open image")
-            .with_guessed_format()
-            .expect("Failed to guess image format");
+    // Previews and tiles are 8-bit and sRGB, only the high-res copy keeps the full depth and the profile
+    let (preview_image, preview_converted) = to_srgb_preview(&highres_image, icc_profile.as_ref());
 
-        reader.no_limits();
-        let source = reader.decode()
-            .expect("Failed to decode image");
+    // The camera's own rendering of a RAW file looks better than ours and is far smaller to scale down
+    let lowres_source = embedded_preview
+        .filter(|preview| fits_lowres(preview, (highres_width, highres_height), lowres_dimensions))
+        .map(|preview| to_preview_image(&preview));
+    create_lowres_image(
+        lowres_source.as_ref().unwrap_or(&preview_image),
+        &paths.lowres,
+        lowres_dimensions,
+        options,
+    )?;
+    let renditions = create_renditions(&preview_image, &paths, options)?;
 
-        // Step 2: Converting to RGB and generating hash
-        current_step += 1;
-        let _ = channel.send(tauri::ipc::InvokeResponseBody::Json(serde_json::json!({
-            "event": "progress",
-            "data": {
-                "percentage": calculate_progress(current_step, total_steps),
-                "step": "Processing image"
-            }
-        }).to_string()));
+    // Step 4: Saving high-res version
+    on_step("Saving high-res version")?;
 
-        let highres_image:Image<Vec<u8>, 3> = Image::<_, 3>::build(source.width(), source.height()).buf(source.into_rgb8().into_raw());
-        let hash = get_image_hash(&highres_image);
+    if !paths.highres.exists() {
+        file_utils::write_atomically(&paths.highres, |path| {
+            tiff_writer::write_tiff(&highres_image, path, Some(&resolution), icc_profile.as_ref()).map_err(std::io::Error::other)
+        })
+        .map_err(ImportError::Save)?;
+    }
 
-        // Step 3: Creating low-res version
-        current_step += 1;
-        let _ = channel.send(tauri::ipc::InvokeResponseBody::Json(serde_json::json!({
-            "event": "progress",
-            "data": {
-                "percentage": calculate_progress(current_step, total_steps),
-                "step": "Creating low-res version"
-            }
-        }).to_string()));
-
-        let lowres_destination = lowres_dir.join(hash.clone() + ".png");
-        let highres_destination = highres_dir.join(hash.clone() + ".tiff");
-
-        let highres_width = highres_image.width();
-        let highres_height = highres_image.height();
-        
-        // Calculate dimensions and decide if we need a lowres version
-        let lowres_info = calculate_new_dimensions(&highres_image);
-        
-        let (lowres_path, lowres_width, lowres_height) = if let Some((width, height)) = lowres_info {
-            // Create lowres version only if needed
-            get_lowres_image(&mut highres_image.clone(), &lowres_destination, width, height);
-            (lowres_destination.to_str().unwrap(), width, height)
-        } else {
-            // Use highres path for both if image is small enough
-            (highres_destination.to_str().unwrap(), highres_width, highres_height)
-        };
+    // The raster is only one rendering of an SVG, keep the document to render it again at other sizes
+    let vector = if svg::is_svg(Path::new(file)) {
+        let vector_destination = svg::keep_vector(Path::new(file), &dirs.highres, &hash)?;
 
-        // Step 4: Saving high-res version
-        current_step += 1;
-        let _ = channel.send(tauri::ipc::InvokeResponseBody::Json(serde_json::json!({
-            "event": "progress",
-            "data": {
-                "percentage": calculate_progress(current_step, total_steps),
-                "step": "Saving high-res version"
-            }
-        }).to_string()));
+        json!({
+            "path": vector_destination.to_str().unwrap(),
+            "dpi": options.svg_dpi,
+            "target_size": options.svg_target_size
+        })
+    } else {
+        serde_json::Value::Null
+    };
+
+    let tile_manifest = generate_tiles(&preview_image, &paths, pipeline)?;
 
-        if !highres_destination.exists() {
-            // Save as RGB for now
-            highres_image.save(&highres_destination);
+    let color = highres_image.color();
+    drop(highres_image);
+    drop(preview_image);
+    drop(lowres_source);
+
+    let renditions = rendition_entries(&renditions, &paths, options);
+    let mut first_page = page_entry(0, sequence.first_delay_ms, &paths, (highres_width, highres_height), lowres_dimensions);
+    first_page["renditions"] = json!(renditions);
+    let mut page_entries = vec![first_page];
+    page_entries.extend(
+        later_pages
+            .iter()
+            .map(|page| page.entry(&CachePaths::for_page(dirs, &hash, page.index, options), options)),
+    );
+
+    // Step 5: Getting image metadata
+    on_step("Extracting metadata")?;
+
+    let geometry = Geometry {
+        raw: raw_dimensions,
+        orientation,
+        resolution,
+        highres: (highres_width, highres_height),
+        lowres: lowres_dimensions,
+    };
+    let mut output = build_output(&hash, &paths, &geometry, color, &tile_manifest)?;
+    if !vector.is_null() {
+        output["vector"] = vector;
+    }
+    output["metadata"] = json!(metadata::read_metadata(Path::new(file)));
+    output["icc_profile"] = match &icc_profile {
+        Some(icc_profile) => {
+            let mut info = json!(icc_profile);
+            info["preview_converted"] = json!(preview_converted);
+            info
         }
+        None => serde_json::Value::Null,
+    };
+    output["renditions"] = json!(renditions);
 
-        // Step 5: Getting image metadata
-        current_step += 1;
-        let _ = channel.send(tauri::ipc::InvokeResponseBody::Json(serde_json::json!({
-            "event": "progress",
-            "data": {
-                "percentage": calculate_progress(current_step, total_steps),
-                "step": "Extracting metadata"
+    // A single frame GIF or WebP plays like a still image
+    let animated = sequence.animated && page_entries.len() > 1;
+    output["animation"] = if animated {
+        json!({
+            "frame_count": page_entries.len(),
+            "duration_ms": page_entries.iter().filter_map(|page| page["delay_ms"].as_u64()).sum::<u64>()
+        })
+    } else {
+        serde_json::Value::Null
+    };
+    output["pages"] = json!(page_entries);
+
+    Ok(finish_output(file, output, &mut fingerprint, pipeline))
+}
+
+fn generate_tiles(image: &DynImage<Vec<u8>>, paths: &CachePaths, pipeline: &Pipeline) -> Result<serde_json::Value, ImportError> {
+    let cancel = pipeline.cancel;
+
+    if !pipeline.options.generate_tiles {
+        return Ok(serde_json::Value::Null);
+    }
+
+    tiles::generate_tile_pyramid(image, &paths.tiles, pipeline.options.tile_size, pipeline.options.resize_backend, cancel)
+        .map_err(|e| if cancel.is_cancelled() { ImportError::Cancelled } else { ImportError::Tiles(e) })
+}
+
+/// What caching one page after the first produced, listed in `pages` once it has its final name.
+struct SavedPage {
+    index: usize,
+    delay_ms: Option<u32>,
+    highres: (u32, u32),
+    lowres: (u32, u32),
+    renditions: Vec<Rendition>,
+}
+
+impl SavedPage {
+    fn entry(&self, paths: &CachePaths, options: &ImportOptions) -> serde_json::Value {
+        let mut entry = page_entry(self.index, self.delay_ms, paths, self.highres, self.lowres);
+        entry["renditions"] = json!(rendition_entries(&self.renditions, paths, options));
+        entry
+    }
+}
+
+/// Caches one page after the first in all three copies, under the directory `name` of each.
+fn save_page(
+    image: &DynamicImage,
+    index: usize,
+    delay_ms: Option<u32>,
+    resolution: &Resolution,
+    icc_profile: Option<&IccProfile>,
+    name: &str,
+    pipeline: &Pipeline,
+) -> Result<SavedPage, ImportError> {
+    let options = pipeline.options;
+    let paths = CachePaths::for_page(&pipeline.dirs, name, index, options);
+
+    for page_dir in [paths.highres.parent(), paths.lowres.parent()].into_iter().flatten() {
+        file_utils::create_dir_if_not_exists(page_dir);
+    }
+
+    let (width, height) = (image.width(), image.height());
+    let lowres_dimensions = calculate_new_dimensions(width, height, options.preview_size).unwrap_or((width, height));
+
+    let (preview_image, _) = to_srgb_preview(image, icc_profile);
+    create_lowres_image(
+        &preview_image,
+        &paths.lowres,
+        lowres_dimensions,
+        options,
+    )?;
+    let renditions = create_renditions(&preview_image, &paths, options)?;
+
+    if !paths.highres.exists() {
+        file_utils::write_atomically(&paths.highres, |path| {
+            tiff_writer::write_tiff(image, path, Some(resolution), icc_profile).map_err(std::io::Error::other)
+        })
+        .map_err(ImportError::Save)?;
+    }
+
+    generate_tiles(&preview_image, &paths, pipeline)?;
+
+    Ok(SavedPage {
+        index,
+        delay_ms,
+        highres: (width, height),
+        lowres: lowres_dimensions,
+        renditions,
+    })
+}
+
+fn page_entry(
+    index: usize,
+    delay_ms: Option<u32>,
+    paths: &CachePaths,
+    (highres_width, highres_height): (u32, u32),
+    (lowres_width, lowres_height): (u32, u32),
+) -> serde_json::Value {
+    json!({
+        "index": index,
+        "delay_ms": delay_ms,
+        "paths": {
+            "highres": paths.highres.to_str().unwrap(),
+            "lowres": paths.lowres.to_str().unwrap(),
+            "tiles": paths.tiles.to_str().unwrap()
+        },
+        "dimensions": {
+            "highres": {
+                "width": highres_width,
+                "height": highres_height
+            },
+            "lowres": {
+                "width": lowres_width,
+                "height": lowres_height
             }
-        }).to_string()));
+        }
+    })
+}
 
-        let highres_size_str = get_image_data(highres_destination.clone());
-        let lowres_size_str = if lowres_info.is_some() {
-            get_image_data(lowres_destination.clone())
-        } else {
-            highres_size_str.clone()
-        };
-        let dpi = get_dpi(&highres_destination);
+/// Sizes of one image from the source file to the preview.
+struct Geometry {
+    /// As stored in the source file, before the EXIF orientation is applied
+    raw: (u32, u32),
+    orientation: Orientation,
+    /// Of the upright image, X and Y are already swapped when it was turned
+    resolution: Resolution,
+    highres: (u32, u32),
+    lowres: (u32, u32),
+}
+
+/// Builds the result entry for an image whose cached copies are all on disk.
+fn build_output(
+    hash: &str,
+    paths: &CachePaths,
+    geometry: &Geometry,
+    color: ColorType,
+    tile_manifest: &serde_json::Value,
+) -> Result<serde_json::Value, ImportError> {
+    let highres_size_str = get_image_data(&paths.highres)?;
+    let lowres_size_str = get_image_data(&paths.lowres)?;
 
-        let filename = file.split("/").last().unwrap();
+    let (raw_width, raw_height) = geometry.raw;
+    let (highres_width, highres_height) = geometry.highres;
+    let (lowres_width, lowres_height) = geometry.lowres;
 
-        let output = json!({
-            "filename": filename,
-            "dpi": dpi,
-            "paths": {
-                "highres": highres_destination.to_str().unwrap(),
-                "lowres": lowres_path
+    Ok(json!({
+        "hash": hash,
+        "dpi": geometry.resolution.dpi(),
+        "resolution": geometry.resolution,
+        "orientation": geometry.orientation.to_exif(),
+        "color": color_info(color),
+        "paths": {
+            "highres": paths.highres.to_str().unwrap(),
+            "lowres": paths.lowres.to_str().unwrap(),
+            "tiles": paths.tiles.to_str().unwrap()
+        },
+        "sizes": {
+            "highres": highres_size_str,
+            "lowres": lowres_size_str
+        },
+        "dimensions": {
+            "raw": {
+                "width": raw_width,
+                "height": raw_height
             },
-            "sizes": {
-                "highres": highres_size_str,
-                "lowres": lowres_size_str
+            "highres": {
+                "width": highres_width,
+                "height": highres_height
             },
-            "dimensions": {
-                "highres": {
-                    "width": highres_width,
-                    "height": highres_height
-                },
-                "lowres": {
-                    "width": lowres_width,
-                    "height": lowres_height
-                }
+            "lowres": {
+                "width": lowres_width,
+                "height": lowres_height
             }
-        });
+        },
+        "tiles": {
+            "tile_size": tile_manifest["tile_size"],
+            "max_level": tile_manifest["max_level"]
+        }
+    }))
+}
+
+/// The stored result for an already imported image, as long as every copy this import
+/// would produce is still on disk. `None` means the index is stale and the file is decoded again.
+fn cached_output(hash: &str, pipeline: &Pipeline) -> Option<serde_json::Value> {
+    let output = pipeline.index.entry(hash).ok()??;
+
+    // Entries from before orientation was applied may hold sideways copies
+    if !output["orientation"].is_u64() {
+        return None;
+    }
 
-        results.push(output);
+    // Entries from before the resolution was read from the container only have EXIF's guess
+    if !output["resolution"].is_object() {
+        return None;
     }
 
+    // Entries from before ICC profiles were read have previews in the source's colour space
+    output.get("icc_profile")?;
+
+    // Entries from before pages were cached only know about the first one
+    let pages = output["pages"].as_array()?;
+
+    // The first page is the image itself, so this covers its copies as well
+    for page in pages {
+        let index = page["index"].as_u64()? as usize;
+        let expected = CachePaths::for_page(&pipeline.dirs, hash, index, pipeline.options);
+
+        let highres_exists = page["paths"]["highres"].as_str().is_some_and(|path| Path::new(path).exists());
+        let lowres_exists = page["paths"]["lowres"].as_str() == expected.lowres.to_str() && expected.lowres.exists();
+        let tiles_exist = !pipeline.options.generate_tiles
+            || page["paths"]["tiles"].as_str().and_then(|path| tiles::cached_manifest(Path::new(path), pipeline.options.tile_size)).is_some();
+
+        if !(highres_exists && lowres_exists && tiles_exist) {
+            return None;
+        }
+
+        let expected_renditions: HashSet<PathBuf> = pipeline.options.renditions.iter()
+            .map(|size| expected.rendition(*size, pipeline.options))
+            .collect();
+        let cached_renditions: HashSet<PathBuf> = page["renditions"].as_array().into_iter().flatten()
+            .filter_map(|rendition| rendition["path"].as_str().map(PathBuf::from))
+            .collect();
+
+        if expected_renditions != cached_renditions || !expected_renditions.iter().all(|path| path.exists()) {
+            return None;
+        }
+    }
+
+    // An SVG rendered at another size than the options ask for is rendered again
+    let vector = &output["vector"];
+    if vector.is_object() {
+        let vector_exists = vector["path"].as_str().is_some_and(|path| Path::new(path).exists());
+        let same_size = vector["dpi"].as_f64() == Some(pipeline.options.svg_dpi as f64)
+            && vector["target_size"].as_u64() == pipeline.options.svg_target_size.map(u64::from);
+
+        if !(vector_exists && same_size) {
+            return None;
+        }
+    }
+
+    Some(output)
+}
+
+/// The stored result for a file the index already knows, see [`cached_output`].
+fn lookup_cached(file: &str, fingerprint: &mut SourceFingerprint, pipeline: &Pipeline) -> Option<serde_json::Value> {
+    match pipeline.index.lookup(fingerprint) {
+        Ok(hash) => cached_output(&hash?, pipeline),
+        Err(e) => {
+            println!("Cache index lookup failed for {}: {}", file, e);
+            None
+        }
+    }
+}
+
+/// Stamps the source file onto a result and records it in the index. Only called once every
+/// copy is on disk, so the index never points a file at copies that are still being written.
+fn finish_output(
+    file: &str,
+    mut output: serde_json::Value,
+    fingerprint: &mut SourceFingerprint,
+    pipeline: &Pipeline,
+) -> serde_json::Value {
+    output["filename"] = json!(display_filename(file));
+    output["source_path"] = json!(file);
+
+    // Cached entries only match when their previews were made with the same settings
+    let options = pipeline.options;
+    output["preview"] = json!({
+        "size": options.preview_size,
+        "filter": options.resample_filter,
+        "backend": options.resize_backend,
+        "format": options.preview_format
+    });
+
+    if let Err(e) = pipeline.index.record_entry(&mut output) {
+        println!("Failed to record {} in the cache index: {}", file, e);
+    }
+    if let Some(hash) = output["hash"].as_str() {
+        if let Err(e) = pipeline.index.record(fingerprint, hash) {
+            println!("Failed to record {} in the cache index: {}", file, e);
+        }
+    }
+
+    output
+}
+
+/// Runs the import pipeline over `paths`, caching every image under `image_cache_dir`.
+/// Files are processed in parallel on a dedicated pool of `options.workers` threads.
+/// Progress, error and completion events are handed to `on_event` in the same shape the
+/// frontend receives them over its `Channel`. A file that fails does not stop the batch;
+/// cancelling `cancel` does, and the entries finished so far are returned. Images are marked
+/// as being written in `pins` while they are, so eviction from other jobs leaves them alone.
+pub fn process_images(
+    paths: &[String],
+    options: &ImportOptions,
+    image_cache_dir: &Path,
+    index: &CacheIndex,
+    pins: &CachePins,
+    cancel: &CancellationToken,
+    on_event: impl Fn(serde_json::Value) + Sync,
+) -> Result<Vec<ImportEntry>, String> {
+    let pipeline = Pipeline {
+        options,
+        dirs: prepare_directories(image_cache_dir),
+        cancel,
+        budget: MemoryBudget::new(options.memory_budget_bytes()),
+        claims: HashClaims::default(),
+        index,
+        pins,
+    };
+    let workers = options.workers.unwrap_or_else(pool::default_worker_count);
+    let thread_pool = pool::build_pool(workers)?;
+
+    let start_time = Instant::now();
+    
+    let total_steps = paths.len() * STEPS_PER_FILE;
+
+    // Steps are counted and sent under one lock so the overall percentage never goes backwards
+    let current_step = Mutex::new(0);
+    let report_steps = |steps: usize, step: &str, file: serde_json::Value| {
+        let mut current_step = current_step.lock().unwrap();
+        *current_step += steps;
+        on_event(progress_event(*current_step, total_steps, step, file));
+    };
+
+    let outcomes: Vec<Option<ImportEntry>> = thread_pool.install(|| {
+        paths.par_iter().enumerate().map(|(index, file)| {
+            let mut file_step = 0;
+            let result = process_image(file, &pipeline, &mut |step| {
+                if cancel.is_cancelled() {
+                    return Err(ImportError::Cancelled);
+                }
+
+                file_step += 1;
+                report_steps(1, step, file_progress(index, file, file_step));
+                Ok(())
+            });
+
+            match result {
+                Ok(output) => {
+                    // Cache hits finish early, catch up on the steps they did not need
+                    if file_step < STEPS_PER_FILE {
+                        report_steps(STEPS_PER_FILE - file_step, "Loaded from cache", file_progress(index, file, STEPS_PER_FILE));
+                    }
+
+                    Some(ImportEntry::Ok(output))
+                }
+                Err(ImportError::Cancelled) => None,
+                Err(error) => {
+                    let failure = ImportFailure::new(file, &error);
+                    on_event(error_event(&failure));
+
+                    // Skip the steps this file never reached so the percentage still ends at 100
+                    report_steps(STEPS_PER_FILE - file_step, "Skipped failed image", file_progress(index, file, STEPS_PER_FILE));
+
+                    Some(ImportEntry::Error(failure))
+                }
+            }
+        }).collect()
+    });
+
+    let was_cancelled = outcomes.iter().any(Option::is_none);
+    let results: Vec<ImportEntry> = outcomes.into_iter().flatten().collect();
+
     let end_time = Instant::now();
     let time_taken = end_time.duration_since(start_time);
+
+    if was_cancelled {
+        on_event(json!({
+            "event": "cancelled",
+            "data": {
+                "time_taken": format!("{:.2?}", time_taken),
+                "total_files": paths.len(),
+                "processed_files": results.len(),
+                "results": results
+            }
+        }));
+
+        return Ok(results);
+    }
+
+    let failed_files = results.iter().filter(|entry| matches!(entry, ImportEntry::Error(_))).count();
     
     // Send completion message
-    let _ = channel.send(tauri::ipc::InvokeResponseBody::Json(serde_json::json!({
+    on_event(json!({
         "event": "complete",
         "data": {
             "time_taken": format!("{:.2?}", time_taken),
-            "total_files": selected_files.len()
+            "total_files": paths.len(),
+            "failed_files": failed_files
         }
-    }).to_string()));
+    }));
 
 	Ok(results)
 }
+
+#[tauri::command]
+pub async fn import_images(
+    paths: Vec<String>,
+    options: Option<ImportOptions>,
+    channel: Channel,
+    registry: State<'_, JobRegistry>,
+    index: State<'_, CacheIndex>,
+    pins: State<'_, CachePins>,
+) -> Result<Vec<ImportEntry>, String> {
+    let image_cache_dir = IMAGE_CACHE_DIR.lock().unwrap().clone();
+    let options = options.unwrap_or_default().validated();
+
+    let index = index.inner().clone();
+    let pins = pins.inner().clone();
+    let (job_id, cancel) = registry.register();
+    send_event(&channel, json!({
+        "event": "started",
+        "data": {
+            "job_id": job_id,
+            "total_files": paths.len()
+        }
+    }));
+
+    // Decoding, hashing and scaling are CPU bound, keep them off the async runtime
+    let results = tokio::task::spawn_blocking(move || {
+        let results = process_images(&paths, &options, &image_cache_dir, &index, &pins, &cancel, |event| send_event(&channel, event))?;
+
+        cache::evict_after_import(&image_cache_dir, &index, &pins, &results, |event| send_event(&channel, event));
+
+        Ok(results)
+    })
+    .await;
+    registry.finish(job_id);
+
+    results.map_err(|e| format!("Import task failed: {}", e))?
+}
+
+#[tauri::command]
+pub async fn load_and_resize_images(
+    app_handle: AppHandle,
+    channel: Channel,
+    registry: State<'_, JobRegistry>,
+    index: State<'_, CacheIndex>,
+    pins: State<'_, CachePins>,
+) -> Result<Vec<ImportEntry>, String> {
+    let selected_files = file_utils::open_image_dialog(app_handle);
+
+    import_images(selected_files, None, channel, registry, index, pins).await
+}
@@ -0,0 +1,402 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use rexiv2::Metadata;
+use serde::Serialize;
+use serde_json::json;
+use tauri::State;
+
+use crate::image::cache;
+use crate::image::cache_index::CacheIndex;
+
+// Each field is read from the first of its tags that holds a value, in this order
+pub const TITLE_TAGS: &[&str] = &["Xmp.dc.title", "Iptc.Application2.ObjectName", "Exif.Image.XPTitle"];
+pub const DESCRIPTION_TAGS: &[&str] = &["Xmp.dc.description", "Iptc.Application2.Caption", "Exif.Image.ImageDescription"];
+pub const COPYRIGHT_TAGS: &[&str] = &["Xmp.dc.rights", "Iptc.Application2.Copyright", "Exif.Image.Copyright"];
+pub const CREATOR_TAGS: &[&str] = &["Xmp.dc.creator", "Iptc.Application2.Byline", "Exif.Image.Artist"];
+pub const KEYWORD_TAGS: &[&str] = &["Xmp.dc.subject", "Iptc.Application2.Keywords"];
+pub const RATING_TAGS: &[&str] = &["Xmp.xmp.Rating", "Exif.Image.Rating"];
+pub const CAPTURE_DATE_TAGS: &[&str] = &["Exif.Photo.DateTimeOriginal", "Xmp.exif.DateTimeOriginal", "Xmp.photoshop.DateCreated", "Exif.Image.DateTime"];
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CameraInfo {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub serial_number: Option<String>,
+    pub software: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LensInfo {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub serial_number: Option<String>,
+    pub focal_length_mm: Option<f64>,
+    pub focal_length_35mm: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ExposureInfo {
+    /// As photographers write it, e.g. `1/250`
+    pub exposure_time: Option<String>,
+    pub exposure_seconds: Option<f64>,
+    pub f_number: Option<f64>,
+    pub iso: Option<u32>,
+    pub exposure_bias: Option<String>,
+    pub exposure_program: Option<String>,
+    pub metering_mode: Option<String>,
+    pub flash: Option<String>,
+    pub white_balance: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CaptureInfo {
+    /// ISO 8601 local time, e.g. `2024-05-01T14:32:10`
+    pub date_time: Option<String>,
+    /// Offset from UTC the camera recorded, e.g. `+02:00`
+    pub offset: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct GpsPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RightsInfo {
+    pub creator: Option<String>,
+    pub copyright: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DescriptiveInfo {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub keywords: Vec<String>,
+    pub rating: Option<i32>,
+}
+
+/// What a source file says about itself, gathered from its EXIF, IPTC and XMP blocks.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImageMetadata {
+    pub camera: CameraInfo,
+    pub lens: LensInfo,
+    pub exposure: ExposureInfo,
+    pub capture: CaptureInfo,
+    pub gps: Option<GpsPosition>,
+    pub rights: RightsInfo,
+    pub descriptive: DescriptiveInfo,
+    /// Every XMP property, as exiv2 displays it
+    pub xmp: BTreeMap<String, String>,
+}
+
+/// Every tag in the file as exiv2 displays it, grouped by block.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RawTags {
+    pub exif: BTreeMap<String, String>,
+    pub iptc: BTreeMap<String, String>,
+    pub xmp: BTreeMap<String, String>,
+}
+
+// Language alternatives come back as `lang="x-default" Text`
+fn strip_language(value: &str) -> &str {
+    match value.strip_prefix("lang=\"") {
+        Some(rest) => rest.split_once("\" ").map(|(_, text)| text).unwrap_or(rest),
+        None => value,
+    }
+}
+
+fn non_empty(value: String) -> Option<String> {
+    let value = strip_language(value.trim()).trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+pub(crate) fn tag_string(metadata: &Metadata, tag: &str) -> Option<String> {
+    if !metadata.has_tag(tag) {
+        return None;
+    }
+
+    metadata.get_tag_string(tag).ok().and_then(non_empty)
+}
+
+fn tag_display(metadata: &Metadata, tag: &str) -> Option<String> {
+    if !metadata.has_tag(tag) {
+        return None;
+    }
+
+    metadata
+        .get_tag_interpreted_string(tag)
+        .or_else(|_| metadata.get_tag_string(tag))
+        .ok()
+        .and_then(non_empty)
+}
+
+pub(crate) fn first_tag(metadata: &Metadata, tags: &[&str]) -> Option<String> {
+    tags.iter().find_map(|tag| tag_string(metadata, tag))
+}
+
+/// Values of a list tag such as keywords, read from every tag in `tags` and deduplicated.
+pub(crate) fn tag_list(metadata: &Metadata, tags: &[&str]) -> Vec<String> {
+    let mut values: Vec<String> = Vec::new();
+
+    for tag in tags.iter().filter(|tag| metadata.has_tag(tag)) {
+        // Every entry of a bag, sequence or repeated IPTC tag is one value, commas and all
+        for value in metadata.get_tag_multiple_strings(tag).unwrap_or_default().into_iter().filter_map(non_empty) {
+            if !values.contains(&value) {
+                values.push(value);
+            }
+        }
+    }
+
+    values
+}
+
+/// EXIF writes dates as `2024:05:01 14:32:10`, XMP already uses ISO 8601.
+pub(crate) fn iso_date_time(value: &str) -> String {
+    match value.split_once(' ') {
+        Some((date, time)) if date.len() == 10 => format!("{}T{}", date.replace(':', "-"), time),
+        _ => value.to_string(),
+    }
+}
+
+fn exposure_time(metadata: &Metadata) -> (Option<String>, Option<f64>) {
+    let Some(ratio) = metadata.get_exposure_time() else {
+        return (None, None);
+    };
+
+    let (numerator, denominator) = (*ratio.numer(), *ratio.denom());
+    if numerator <= 0 || denominator <= 0 {
+        return (None, None);
+    }
+
+    let seconds = numerator as f64 / denominator as f64;
+    let display = if seconds >= 1.0 {
+        format!("{}", (seconds * 10.0).round() / 10.0)
+    } else {
+        format!("1/{}", (1.0 / seconds).round())
+    };
+
+    (Some(display), Some(seconds))
+}
+
+fn read_gps(metadata: &Metadata) -> Option<GpsPosition> {
+    let gps = metadata.get_gps_info()?;
+
+    Some(GpsPosition {
+        latitude: gps.latitude,
+        longitude: gps.longitude,
+        altitude: metadata.has_tag("Exif.GPSInfo.GPSAltitude").then_some(gps.altitude),
+    })
+}
+
+fn read_xmp(metadata: &Metadata) -> BTreeMap<String, String> {
+    metadata
+        .get_xmp_tags()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|tag| tag_display(metadata, &tag).map(|value| (tag, value)))
+        .collect()
+}
+
+fn extract(metadata: &Metadata) -> ImageMetadata {
+    let (exposure_time, exposure_seconds) = exposure_time(metadata);
+
+    ImageMetadata {
+        camera: CameraInfo {
+            make: tag_string(metadata, "Exif.Image.Make"),
+            model: tag_string(metadata, "Exif.Image.Model"),
+            serial_number: first_tag(metadata, &["Exif.Photo.BodySerialNumber", "Xmp.aux.SerialNumber"]),
+            software: first_tag(metadata, &["Exif.Image.Software", "Xmp.xmp.CreatorTool"]),
+        },
+        lens: LensInfo {
+            make: tag_string(metadata, "Exif.Photo.LensMake"),
+            model: first_tag(metadata, &["Exif.Photo.LensModel", "Xmp.aux.Lens"]),
+            serial_number: first_tag(metadata, &["Exif.Photo.LensSerialNumber", "Xmp.aux.LensSerialNumber"]),
+            focal_length_mm: metadata.get_focal_length(),
+            focal_length_35mm: tag_string(metadata, "Exif.Photo.FocalLengthIn35mmFilm").and_then(|value| value.parse().ok()),
+        },
+        exposure: ExposureInfo {
+            exposure_time,
+            exposure_seconds,
+            f_number: metadata.get_fnumber(),
+            iso: metadata.get_iso_speed().filter(|iso| *iso > 0).map(|iso| iso as u32),
+            exposure_bias: tag_display(metadata, "Exif.Photo.ExposureBiasValue"),
+            exposure_program: tag_display(metadata, "Exif.Photo.ExposureProgram"),
+            metering_mode: tag_display(metadata, "Exif.Photo.MeteringMode"),
+            flash: tag_display(metadata, "Exif.Photo.Flash"),
+            white_balance: tag_display(metadata, "Exif.Photo.WhiteBalance"),
+        },
+        capture: CaptureInfo {
+            date_time: first_tag(metadata, CAPTURE_DATE_TAGS).map(|value| iso_date_time(&value)),
+            offset: first_tag(metadata, &["Exif.Photo.OffsetTimeOriginal", "Exif.Photo.OffsetTime"]),
+        },
+        gps: read_gps(metadata),
+        rights: RightsInfo {
+            creator: first_tag(metadata, CREATOR_TAGS),
+            copyright: first_tag(metadata, COPYRIGHT_TAGS),
+        },
+        descriptive: DescriptiveInfo {
+            title: first_tag(metadata, TITLE_TAGS),
+            description: first_tag(metadata, DESCRIPTION_TAGS),
+            keywords: tag_list(metadata, KEYWORD_TAGS),
+            rating: first_tag(metadata, RATING_TAGS).and_then(|value| value.parse().ok()),
+        },
+        xmp: read_xmp(metadata),
+    }
+}
+
+impl ImageMetadata {
+    // Only the fields a sidecar can hold. A field the sidecar has a tag of is taken from it even
+    // when the tag is empty, that is how a field cleared in the sidecar hides the file's value.
+    // Anything it leaves out keeps the file's value
+    fn apply_sidecar(&mut self, metadata: &Metadata) {
+        let sidecar = extract(metadata);
+        let holds = |tags: &[&str]| tags.iter().any(|tag| metadata.has_tag(tag));
+
+        let descriptive = &mut self.descriptive;
+        if holds(TITLE_TAGS) {
+            descriptive.title = sidecar.descriptive.title;
+        }
+        if holds(DESCRIPTION_TAGS) {
+            descriptive.description = sidecar.descriptive.description;
+        }
+        if holds(RATING_TAGS) {
+            descriptive.rating = sidecar.descriptive.rating;
+        }
+        if holds(KEYWORD_TAGS) {
+            descriptive.keywords = sidecar.descriptive.keywords;
+        }
+
+        if holds(CREATOR_TAGS) {
+            self.rights.creator = sidecar.rights.creator;
+        }
+        if holds(COPYRIGHT_TAGS) {
+            self.rights.copyright = sidecar.rights.copyright;
+        }
+        if holds(CAPTURE_DATE_TAGS) {
+            self.capture.date_time = sidecar.capture.date_time;
+        }
+        self.xmp.extend(sidecar.xmp);
+    }
+}
+
+/// `photo.jpg` -> `photo.jpg.xmp`, the name darktable gives sidecars. Unlike Lightroom's
+/// `photo.xmp` it does not collide with the sidecar of a `photo.cr2` shot alongside, so it is
+/// the only name sidecars are written under.
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".xmp");
+    PathBuf::from(name)
+}
+
+pub fn is_sidecar(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("xmp"))
+}
+
+/// The sidecar to read for `path`: its own, otherwise the `photo.xmp` Lightroom or Bridge
+/// wrote, as long as no other file next to it is called `photo` and might be the one it
+/// belongs to.
+pub fn existing_sidecar(path: &Path) -> Option<PathBuf> {
+    if is_sidecar(path) {
+        return None;
+    }
+
+    let sidecar = sidecar_path(path);
+    if sidecar.exists() {
+        return Some(sidecar);
+    }
+
+    let shared = path.with_extension("xmp");
+    (shared.exists() && !shares_stem(path)).then_some(shared)
+}
+
+// Whether another file next to `path` has the same name up to its extension. A folder that
+// cannot be listed counts as shared, a sidecar of unknown owner is better left unread
+fn shares_stem(path: &Path) -> bool {
+    let (Some(parent), Some(stem)) = (path.parent(), path.file_stem()) else {
+        return true;
+    };
+    let Ok(entries) = std::fs::read_dir(parent) else {
+        return true;
+    };
+
+    entries.flatten().map(|entry| entry.path()).any(|sibling| {
+        sibling != path && !is_sidecar(&sibling) && sibling.file_stem() == Some(stem)
+    })
+}
+
+/// Structured metadata of `path`, `None` for formats exiv2 cannot read such as SVG.
+/// Values saved to an XMP sidecar next to the file take precedence over the file's own.
+pub fn read_metadata(path: &Path) -> Option<ImageMetadata> {
+    let mut image_metadata = Metadata::new_from_path(path).ok().map(|metadata| extract(&metadata))?;
+
+    if let Some(sidecar) = existing_sidecar(path) {
+        if let Ok(metadata) = Metadata::new_from_path(&sidecar) {
+            image_metadata.apply_sidecar(&metadata);
+        }
+    }
+
+    Some(image_metadata)
+}
+
+/// Every EXIF, IPTC and XMP tag of `path`, for when the structured fields are not enough.
+pub fn read_raw_tags(path: &Path) -> Result<RawTags, String> {
+    let metadata = Metadata::new_from_path(path).map_err(|e| format!("Failed to read metadata: {}", e))?;
+
+    let dump = |tags: Vec<String>| -> BTreeMap<String, String> {
+        tags.into_iter()
+            .filter_map(|tag| tag_display(&metadata, &tag).map(|value| (tag, value)))
+            .collect()
+    };
+
+    Ok(RawTags {
+        exif: dump(metadata.get_exif_tags().unwrap_or_default()),
+        iptc: dump(metadata.get_iptc_tags().unwrap_or_default()),
+        xmp: dump(metadata.get_xmp_tags().unwrap_or_default()),
+    })
+}
+
+fn metadata_for(hash: &str, raw: bool, index: &CacheIndex) -> Result<serde_json::Value, String> {
+    let mut entry = index.entry(hash)?.ok_or_else(|| format!("No cached image with hash {}", hash))?;
+    let source_path = entry["source_path"].as_str().map(str::to_string);
+
+    // Entries imported before metadata was cached get it read now and stored for next time
+    if entry.get("metadata").is_none() {
+        let source_path = source_path.as_deref().ok_or("Cached image has no source path")?;
+        entry["metadata"] = json!(read_metadata(Path::new(source_path)));
+
+        if let Err(e) = index.record_entry(&mut entry) {
+            println!("Failed to store metadata of {} in the cache index: {}", hash, e);
+        }
+    }
+
+    let raw_tags = if raw {
+        let source_path = source_path.as_deref().ok_or("Cached image has no source path")?;
+        json!(read_raw_tags(Path::new(source_path))?)
+    } else {
+        serde_json::Value::Null
+    };
+
+    Ok(json!({
+        "hash": hash,
+        "source_path": source_path,
+        "metadata": entry["metadata"],
+        "raw": raw_tags
+    }))
+}
+
+#[tauri::command]
+pub async fn get_metadata(
+    hash: String,
+    raw: Option<bool>,
+    index: State<'_, CacheIndex>,
+) -> Result<serde_json::Value, String> {
+    cache::validate_hash(&hash)?;
+    let index = index.inner().clone();
+
+    tokio::task::spawn_blocking(move || metadata_for(&hash, raw.unwrap_or(false), &index))
+        .await
+        .map_err(|e| format!("Metadata task failed: {}", e))?
+}
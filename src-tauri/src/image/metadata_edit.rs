@@ -0,0 +1,373 @@
+use std::path::{Path, PathBuf};
+
+use rexiv2::Metadata;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tauri::State;
+
+use crate::image::cache_index::{CacheIndex, SourceFingerprint};
+use crate::image::error::{ImportEntry, ImportError, ImportFailure};
+use crate::image::metadata::{
+    self, first_tag, tag_list, tag_string, CAPTURE_DATE_TAGS, COPYRIGHT_TAGS, DESCRIPTION_TAGS, KEYWORD_TAGS, RATING_TAGS, TITLE_TAGS,
+};
+
+/// Formats whose own metadata blocks exiv2 can rewrite in place. Anything else takes a sidecar.
+const WRITABLE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "tif", "tiff", "png"];
+
+// Smallest packet exiv2 accepts as an XMP sidecar, filled in once it is opened
+const EMPTY_SIDECAR: &str = "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n\
+ <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\"/>\n\
+</x:xmpmeta>\n\
+<?xpacket end=\"w\"?>\n";
+
+/// Fields to change. Fields left out stay as they are, an empty value removes the field.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MetadataChanges {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub keywords: Option<Vec<String>>,
+    /// 0 to 5 stars, -1 for rejected
+    pub rating: Option<i32>,
+    pub copyright: Option<String>,
+    /// ISO 8601 local time, e.g. `2024-05-01T14:32:10`
+    pub capture_date: Option<String>,
+}
+
+/// Where edits are saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteTarget {
+    /// Into the source file itself, only for JPEG, TIFF and PNG
+    #[default]
+    File,
+    /// Into `<name>.<ext>.xmp` next to the source, which is left untouched
+    Sidecar,
+}
+
+/// New value of one tag. Lists are written as repeated values, not one joined string.
+#[derive(Debug, Clone, PartialEq)]
+enum TagValue {
+    Text(String),
+    List(Vec<String>),
+    Number(i32),
+}
+
+impl TagValue {
+    fn is_empty(&self) -> bool {
+        match self {
+            TagValue::Text(text) => text.is_empty(),
+            TagValue::List(values) => values.is_empty(),
+            TagValue::Number(_) => false,
+        }
+    }
+
+    fn display(&self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+
+        Some(match self {
+            TagValue::Text(text) => text.clone(),
+            TagValue::List(values) => values.join(", "),
+            TagValue::Number(number) => number.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagChange {
+    pub tag: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WriteReport {
+    pub path: String,
+    /// The source itself or its sidecar
+    pub written_to: String,
+    pub dry_run: bool,
+    /// Only tags whose value actually changes
+    pub changes: Vec<TagChange>,
+}
+
+// EXIF wants `2024:05:01 14:32:10`, fractions of a second and offsets live in other tags
+fn exif_date_time(value: &str) -> Result<String, String> {
+    let invalid = || format!("Invalid capture date {:?}, expected e.g. 2024-05-01T14:32:10", value);
+
+    let value = value.get(..19).ok_or_else(invalid)?;
+    let (date, time) = value.split_once('T').ok_or_else(invalid)?;
+    let is_digits = |part: &str, lengths: &[usize], separator: char| {
+        let parts: Vec<&str> = part.split(separator).collect();
+        parts.len() == lengths.len()
+            && parts.iter().zip(lengths).all(|(part, length)| part.len() == *length && part.bytes().all(|b| b.is_ascii_digit()))
+    };
+
+    if !is_digits(date, &[4, 2, 2], '-') || !is_digits(time, &[2, 2, 2], ':') {
+        return Err(invalid());
+    }
+
+    Ok(format!("{} {}", date.replace('-', ":"), time))
+}
+
+/// Every tag the changes touch with the value it gets, in all three blocks so other
+/// applications find the same value whichever block they read.
+fn planned_values(changes: &MetadataChanges) -> Result<Vec<(&'static str, TagValue)>, String> {
+    let mut planned = Vec::new();
+
+    let mut text = |tags: &[&'static str], value: &Option<String>| {
+        if let Some(value) = value {
+            for tag in tags {
+                planned.push((*tag, TagValue::Text(value.trim().to_string())));
+            }
+        }
+    };
+
+    text(&["Xmp.dc.title", "Iptc.Application2.ObjectName"], &changes.title);
+    text(&["Xmp.dc.description", "Iptc.Application2.Caption", "Exif.Image.ImageDescription"], &changes.description);
+    text(&["Xmp.dc.rights", "Iptc.Application2.Copyright", "Exif.Image.Copyright"], &changes.copyright);
+
+    if let Some(capture_date) = &changes.capture_date {
+        let capture_date = capture_date.trim();
+        let exif_date = if capture_date.is_empty() { String::new() } else { exif_date_time(capture_date)? };
+
+        planned.push(("Exif.Photo.DateTimeOriginal", TagValue::Text(exif_date)));
+        planned.push(("Xmp.exif.DateTimeOriginal", TagValue::Text(capture_date.to_string())));
+        planned.push(("Xmp.photoshop.DateCreated", TagValue::Text(capture_date.to_string())));
+    }
+
+    if let Some(keywords) = &changes.keywords {
+        let mut unique: Vec<String> = Vec::new();
+        for keyword in keywords.iter().map(|keyword| keyword.trim()).filter(|keyword| !keyword.is_empty()) {
+            if !unique.iter().any(|existing| existing == keyword) {
+                unique.push(keyword.to_string());
+            }
+        }
+
+        planned.push(("Xmp.dc.subject", TagValue::List(unique.clone())));
+        planned.push(("Iptc.Application2.Keywords", TagValue::List(unique)));
+    }
+
+    if let Some(rating) = changes.rating {
+        if !(-1..=5).contains(&rating) {
+            return Err(format!("Rating must be between -1 and 5, got {}", rating));
+        }
+
+        planned.push(("Xmp.xmp.Rating", TagValue::Number(rating)));
+        // Exif.Image.Rating is an unsigned SHORT with no value for rejected, so it is removed instead
+        let exif_rating = if rating < 0 { TagValue::Text(String::new()) } else { TagValue::Number(rating) };
+        planned.push(("Exif.Image.Rating", exif_rating));
+    }
+
+    Ok(planned)
+}
+
+fn supports_tag(metadata: &Metadata, target: WriteTarget, tag: &str) -> bool {
+    match tag.split('.').next() {
+        Some("Xmp") => metadata.supports_xmp(),
+        Some("Exif") => target == WriteTarget::File && metadata.supports_exif(),
+        Some("Iptc") => target == WriteTarget::File && metadata.supports_iptc(),
+        _ => false,
+    }
+}
+
+fn current_value(metadata: &Metadata, tag: &str, value: &TagValue) -> Option<String> {
+    match value {
+        TagValue::List(_) => {
+            let values = tag_list(metadata, &[tag]);
+            (!values.is_empty()).then(|| values.join(", "))
+        }
+        _ => tag_string(metadata, tag),
+    }
+}
+
+// The tags a field is read from, see `metadata::extract`
+fn field_tags(tag: &str) -> &'static [&'static str] {
+    [TITLE_TAGS, DESCRIPTION_TAGS, COPYRIGHT_TAGS, KEYWORD_TAGS, RATING_TAGS, CAPTURE_DATE_TAGS]
+        .into_iter()
+        .find(|tags| tags.contains(&tag))
+        .unwrap_or_default()
+}
+
+// What a field the sidecar does not hold reads as, which is the source file's own value
+fn source_value(source: &Metadata, tag: &str, value: &TagValue) -> Option<String> {
+    let tags = field_tags(tag);
+
+    match value {
+        TagValue::List(_) => {
+            let values = tag_list(source, tags);
+            (!values.is_empty()).then(|| values.join(", "))
+        }
+        _ => first_tag(source, tags),
+    }
+}
+
+fn set_tag(metadata: &Metadata, target: WriteTarget, tag: &str, value: &TagValue) -> Result<(), String> {
+    // Clearing first also drops the old entries of a list instead of appending to them
+    metadata.clear_tag(tag);
+    if value.is_empty() {
+        // A tag missing from the sidecar falls back to the file's value, an empty one hides it
+        return match target {
+            WriteTarget::File => Ok(()),
+            WriteTarget::Sidecar => metadata.set_tag_string(tag, "").map_err(|e| format!("Failed to clear {}: {}", tag, e)),
+        };
+    }
+
+    let written = match value {
+        TagValue::Text(text) => metadata.set_tag_string(tag, text),
+        TagValue::List(values) => {
+            let values: Vec<&str> = values.iter().map(String::as_str).collect();
+            metadata.set_tag_multiple_strings(tag, &values)
+        }
+        TagValue::Number(number) => metadata.set_tag_numeric(tag, *number),
+    };
+
+    written.map_err(|e| format!("Failed to set {}: {}", tag, e))
+}
+
+fn open_target(path: &Path, target: WriteTarget, dry_run: bool) -> Result<(PathBuf, Option<Metadata>), String> {
+    match target {
+        WriteTarget::File => {
+            let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default().to_lowercase();
+            if !WRITABLE_EXTENSIONS.contains(&extension.as_str()) {
+                return Err(format!("Cannot write metadata into .{} files, save it to an XMP sidecar instead", extension));
+            }
+
+            let metadata = Metadata::new_from_path(path).map_err(|e| e.to_string())?;
+            Ok((path.to_path_buf(), Some(metadata)))
+        }
+        WriteTarget::Sidecar => {
+            if metadata::is_sidecar(path) {
+                return Err("The source is an XMP file already".to_string());
+            }
+            let sidecar = metadata::sidecar_path(path);
+
+            if !sidecar.exists() {
+                // A `photo.xmp` from Lightroom is no longer read once this one exists, so it starts as its copy
+                let shared = metadata::existing_sidecar(path);
+
+                // A dry run compares against the sidecar it would start from without creating one
+                if dry_run {
+                    return Ok((sidecar, shared.and_then(|shared| Metadata::new_from_path(shared).ok())));
+                }
+                let created = match &shared {
+                    Some(shared) => std::fs::copy(shared, &sidecar).map(|_| ()),
+                    None => std::fs::write(&sidecar, EMPTY_SIDECAR),
+                };
+                created.map_err(|e| format!("Failed to create {:?}: {}", sidecar, e))?;
+            }
+
+            let metadata = Metadata::new_from_path(&sidecar).map_err(|e| e.to_string())?;
+            Ok((sidecar, Some(metadata)))
+        }
+    }
+}
+
+/// Applies `changes` to one file. Tags not named in `changes` are carried over as they are,
+/// exiv2 only rewrites the ones that are set or cleared.
+pub fn write_metadata(path: &Path, changes: &MetadataChanges, target: WriteTarget, dry_run: bool) -> Result<WriteReport, String> {
+    if !path.exists() {
+        return Err(format!("{:?} does not exist", path));
+    }
+
+    let planned = planned_values(changes)?;
+    let (written_to, metadata) = open_target(path, target, dry_run)?;
+    // Fields a sidecar does not hold yet read as the file's values, exiv2 cannot read every source
+    let source = match target {
+        WriteTarget::File => None,
+        WriteTarget::Sidecar => Metadata::new_from_path(path).ok(),
+    };
+
+    let mut diff = Vec::new();
+    for (tag, value) in &planned {
+        let holds_field = |metadata: &Metadata| field_tags(tag).iter().any(|tag| metadata.has_tag(tag));
+        let before = match &metadata {
+            Some(metadata) if !supports_tag(metadata, target, tag) => continue,
+            None if !tag.starts_with("Xmp.") => continue,
+            Some(metadata) if target == WriteTarget::File || holds_field(metadata) => current_value(metadata, tag, value),
+            _ => source.as_ref().and_then(|source| source_value(source, tag, value)),
+        };
+
+        let after = value.display();
+        if before != after {
+            diff.push(TagChange { tag: tag.to_string(), before, after });
+        }
+    }
+
+    if let (Some(metadata), false) = (&metadata, dry_run) {
+        if !diff.is_empty() {
+            for (tag, value) in &planned {
+                if diff.iter().any(|change| change.tag == *tag) {
+                    set_tag(metadata, target, tag, value)?;
+                }
+            }
+
+            metadata.save_to_file(&written_to).map_err(|e| format!("Failed to save {:?}: {}", written_to, e))?;
+        }
+    }
+
+    Ok(WriteReport {
+        path: path.to_string_lossy().to_string(),
+        written_to: written_to.to_string_lossy().to_string(),
+        dry_run,
+        changes: diff,
+    })
+}
+
+/// Keeps the cached entry of `path` in step with what was just written, and maps the
+/// rewritten file to the same image so the next import does not decode it again.
+fn refresh_index(path: &str, hash: &str, index: &CacheIndex) -> Result<(), String> {
+    let mut fingerprint = SourceFingerprint::read(path).map_err(|e| e.to_string())?;
+    index.record(&mut fingerprint, hash)?;
+
+    if let Some(mut entry) = index.entry(hash)? {
+        entry["metadata"] = json!(metadata::read_metadata(Path::new(path)));
+        index.record_entry(&mut entry)?;
+    }
+
+    Ok(())
+}
+
+fn edit(paths: &[String], changes: &MetadataChanges, target: WriteTarget, dry_run: bool, index: &CacheIndex) -> Vec<ImportEntry> {
+    paths
+        .iter()
+        .map(|path| {
+            // Looked up before writing, an in-file edit changes the fingerprint the index knows
+            let hash = SourceFingerprint::read(path)
+                .ok()
+                .and_then(|mut fingerprint| index.lookup(&mut fingerprint).ok().flatten());
+
+            match write_metadata(Path::new(path), changes, target, dry_run) {
+                Ok(report) => {
+                    if let (Some(hash), false) = (&hash, dry_run || report.changes.is_empty()) {
+                        if let Err(e) = refresh_index(path, hash, index) {
+                            println!("Failed to update the cache index for {}: {}", path, e);
+                        }
+                    }
+
+                    ImportEntry::Ok(json!(report))
+                }
+                Err(e) => ImportEntry::Error(ImportFailure::new(path, &ImportError::WriteMetadata(e))),
+            }
+        })
+        .collect()
+}
+
+#[tauri::command]
+pub async fn edit_metadata(
+    paths: Vec<String>,
+    changes: MetadataChanges,
+    target: Option<WriteTarget>,
+    dry_run: Option<bool>,
+    index: State<'_, CacheIndex>,
+) -> Result<Vec<ImportEntry>, String> {
+    let index = index.inner().clone();
+    let target = target.unwrap_or_default();
+    let dry_run = dry_run.unwrap_or(false);
+
+    tokio::task::spawn_blocking(move || edit(&paths, &changes, target, dry_run, &index))
+        .await
+        .map_err(|e| format!("Metadata task failed: {}", e))
+}
@@ -0,0 +1,345 @@
+use std::path::{Path, PathBuf};
+
+use rexiv2::Metadata;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tauri::State;
+
+use crate::image::cache;
+use crate::image::cache_index::CacheIndex;
+use crate::image::error::{ImportEntry, ImportError, ImportFailure};
+use crate::image::formats::{self, OutputFormat};
+use crate::image::metadata;
+
+/// Tags that give away where a photo was taken, coordinates as well as place names.
+const LOCATION_TAGS: &[&str] = &[
+    "Exif.GPSInfo.*",
+    "Xmp.exif.GPS*",
+    "Iptc.Application2.City",
+    "Iptc.Application2.SubLocation",
+    "Iptc.Application2.ProvinceState",
+    "Iptc.Application2.CountryName",
+    "Iptc.Application2.CountryCode",
+    "Xmp.photoshop.City",
+    "Xmp.photoshop.State",
+    "Xmp.photoshop.Country",
+    "Xmp.iptc.Location",
+    "Xmp.iptc.CountryCode",
+    "Xmp.iptcExt.LocationCreated*",
+    "Xmp.iptcExt.LocationShown*",
+];
+
+/// Tags that identify the camera, lens or person that took it.
+const DEVICE_TAGS: &[&str] = &[
+    "Exif.Photo.BodySerialNumber",
+    "Exif.Photo.LensSerialNumber",
+    "Exif.Photo.CameraOwnerName",
+    "Exif.Photo.ImageUniqueID",
+    "Exif.Image.CameraSerialNumber",
+    "Xmp.aux.SerialNumber",
+    "Xmp.aux.LensSerialNumber",
+    "Xmp.aux.OwnerName",
+    "Xmp.aux.ImageNumber",
+    "Xmp.exifEX.BodySerialNumber",
+    "Xmp.exifEX.LensSerialNumber",
+    "Xmp.exifEX.CameraOwnerName",
+    // Who took it
+    "Exif.Image.Artist",
+    "Iptc.Application2.Byline",
+    "Xmp.dc.creator",
+    // Maker notes are never copied, but listing them makes the report say why
+    "Exif.Canon.SerialNumber",
+    "Exif.Canon.OwnerName",
+    "Exif.Nikon3.SerialNumber",
+    "Exif.Nikon3.SerialNO",
+    "Exif.Fujifilm.SerialNumber",
+    "Exif.Olympus.SerialNumber",
+    "Exif.OlympusEq.SerialNumber",
+    "Exif.Pentax.SerialNumber",
+    "Exif.Panasonic.InternalSerialNumber",
+];
+
+/// Tags that record how the image was edited, with what and on which machine, and what it
+/// was made from.
+const HISTORY_TAGS: &[&str] = &[
+    "Xmp.xmpMM.*",
+    "Xmp.photoshop.History",
+    "Xmp.photoshop.DocumentAncestors",
+    "Xmp.crs.*",
+    "Xmp.crss.*",
+    "Xmp.lr.*",
+    "Exif.Image.Software",
+    "Exif.Image.ProcessingSoftware",
+    "Exif.Image.HostComputer",
+    "Xmp.xmp.CreatorTool",
+    "Xmp.tiff.Software",
+];
+
+/// Never carried over whatever the policy says: the exported pixels are already upright,
+/// and sizes or thumbnails of the source would describe a different image.
+const NEVER_COPIED: &[&str] = &[
+    "Exif.Image.Orientation",
+    "Exif.Thumbnail.*",
+    "Exif.Image.ImageWidth",
+    "Exif.Image.ImageLength",
+    "Exif.Photo.PixelXDimension",
+    "Exif.Photo.PixelYDimension",
+    "Exif.Image.XResolution",
+    "Exif.Image.YResolution",
+    "Exif.Image.ResolutionUnit",
+    "Xmp.tiff.Orientation",
+    "Xmp.tiff.ImageWidth",
+    "Xmp.tiff.ImageLength",
+    "Xmp.exif.PixelXDimension",
+    "Xmp.exif.PixelYDimension",
+];
+
+/// EXIF groups exiv2 can write on their own. Maker notes and the other vendor groups only
+/// make sense together with the binary blob they were parsed from, so they are dropped.
+const WRITABLE_EXIF_GROUPS: &[&str] = &["Exif.Image.", "Exif.Photo.", "Exif.GPSInfo.", "Exif.Iop."];
+
+/// Which of the source's tags an exported copy keeps.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum MetadataPolicy {
+    KeepAll,
+    /// Same as exporting the cached copy, which holds no metadata of its own
+    #[default]
+    StripAll,
+    /// Keeps everything but location, serial numbers, the photographer's name and editing history
+    StripPrivate,
+    /// Keeps only the listed tags, `*` at the end matches every tag starting with the rest
+    AllowList { tags: Vec<String> },
+}
+
+fn matches(pattern: &str, tag: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => tag.starts_with(prefix),
+        None => tag == pattern,
+    }
+}
+
+fn matches_any(patterns: &[&str], tag: &str) -> bool {
+    patterns.iter().any(|pattern| matches(pattern, tag))
+}
+
+impl MetadataPolicy {
+    pub fn allows(&self, tag: &str) -> bool {
+        match self {
+            MetadataPolicy::KeepAll => true,
+            MetadataPolicy::StripAll => false,
+            MetadataPolicy::StripPrivate => {
+                !matches_any(LOCATION_TAGS, tag) && !matches_any(DEVICE_TAGS, tag) && !matches_any(HISTORY_TAGS, tag)
+            }
+            MetadataPolicy::AllowList { tags } => tags.iter().any(|pattern| matches(pattern, tag)),
+        }
+    }
+}
+
+/// What happened to the source's tags in one exported file.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PolicyReport {
+    pub source_path: Option<String>,
+    pub output_path: String,
+    pub kept: Vec<String>,
+    /// Left out because of the policy
+    pub removed: Vec<String>,
+    /// Allowed, but never carried over: maker notes, thumbnails and the source's own sizes
+    pub dropped: Vec<String>,
+    /// Allowed, but the output format or exiv2 could not take them
+    pub failed: Vec<String>,
+}
+
+fn copyable(tag: &str) -> bool {
+    if matches_any(NEVER_COPIED, tag) {
+        return false;
+    }
+
+    !tag.starts_with("Exif.") || WRITABLE_EXIF_GROUPS.iter().any(|group| tag.starts_with(group))
+}
+
+fn source_tags(metadata: &Metadata) -> Vec<String> {
+    [metadata.get_exif_tags(), metadata.get_iptc_tags(), metadata.get_xmp_tags()]
+        .into_iter()
+        .flat_map(Result::unwrap_or_default)
+        .collect()
+}
+
+fn copy_tag(source: &Metadata, destination: &Metadata, tag: &str) -> Result<(), ()> {
+    let supported = match tag.split('.').next() {
+        Some("Exif") => destination.supports_exif(),
+        Some("Iptc") => destination.supports_iptc(),
+        Some("Xmp") => destination.supports_xmp(),
+        _ => false,
+    };
+    if !supported {
+        return Err(());
+    }
+
+    // Keyword bags and repeatable IPTC fields hold several values under one key
+    if !tag.starts_with("Exif.") {
+        if let Ok(values) = source.get_tag_multiple_strings(tag) {
+            if values.len() > 1 {
+                let values: Vec<&str> = values.iter().map(String::as_str).collect();
+                return destination.set_tag_multiple_strings(tag, &values).map_err(|_| ());
+            }
+        }
+    }
+
+    let value = source.get_tag_string(tag).map_err(|_| ())?;
+    destination.set_tag_string(tag, &value).map_err(|_| ())
+}
+
+/// Copies the tags of `source_path` that `policy` allows into the freshly written `output_path`,
+/// together with the ones saved to its XMP sidecar. With `dry_run` only the report is made
+/// and `output_path` is not touched.
+pub fn apply_policy(source_path: Option<&Path>, output_path: &Path, policy: &MetadataPolicy, dry_run: bool) -> PolicyReport {
+    let mut report = PolicyReport {
+        source_path: source_path.map(|path| path.to_string_lossy().to_string()),
+        output_path: output_path.to_string_lossy().to_string(),
+        ..Default::default()
+    };
+
+    // Sources exiv2 cannot read (SVG, a moved file) have nothing to carry over
+    let Some(source_path) = source_path else {
+        return report;
+    };
+    let sidecar_path = metadata::existing_sidecar(source_path);
+    let sources: Vec<Metadata> = std::iter::once(source_path)
+        .chain(sidecar_path.as_deref())
+        .filter(|path| path.exists())
+        .filter_map(|path| Metadata::new_from_path(path).ok())
+        .collect();
+
+    // The sidecar comes last, so its values overwrite the file's own
+    let mut tags: Vec<(&Metadata, String)> = Vec::new();
+    for source in &sources {
+        for tag in source_tags(source) {
+            tags.retain(|(_, existing)| *existing != tag);
+            tags.push((source, tag));
+        }
+    }
+
+    // Split by the policy before anything else, so a maker note serial shows up as removed or dropped
+    let (allowed, removed): (Vec<_>, Vec<_>) = tags.into_iter().partition(|(_, tag)| policy.allows(tag));
+    report.removed = removed.into_iter().map(|(_, tag)| tag).collect();
+
+    let (allowed, dropped): (Vec<_>, Vec<_>) = allowed.into_iter().partition(|(_, tag)| copyable(tag));
+    report.dropped = dropped.into_iter().map(|(_, tag)| tag).collect();
+
+    if dry_run || allowed.is_empty() {
+        report.kept = allowed.into_iter().map(|(_, tag)| tag).collect();
+        return report;
+    }
+
+    let Ok(destination) = Metadata::new_from_path(output_path) else {
+        report.failed = allowed.into_iter().map(|(_, tag)| tag).collect();
+        return report;
+    };
+
+    for (source, tag) in allowed {
+        match copy_tag(source, &destination, &tag) {
+            Ok(()) => report.kept.push(tag),
+            Err(()) => report.failed.push(tag),
+        }
+    }
+
+    if !report.kept.is_empty() && destination.save_to_file(output_path).is_err() {
+        report.failed.append(&mut report.kept);
+    }
+
+    report
+}
+
+/// Writes the full-resolution copy of `hash` to `output_path` and applies `policy` to it.
+pub fn export_with_policy(
+    hash: &str,
+    output_path: &Path,
+    format: OutputFormat,
+    quality: u8,
+    policy: &MetadataPolicy,
+    dry_run: bool,
+    index: &CacheIndex,
+) -> Result<PolicyReport, String> {
+    let entry = index.entry(hash)?;
+    let source_path = entry
+        .as_ref()
+        .and_then(|entry| entry["source_path"].as_str())
+        .map(PathBuf::from);
+
+    if !dry_run {
+        index.record_access(hash);
+        let image = cache::open_highres_srgb(hash)?;
+        formats::write_image(&image, format, quality, output_path)
+            .map_err(|e| format!("Failed to export image: {}", e))?;
+    }
+
+    Ok(apply_policy(source_path.as_deref(), output_path, policy, dry_run))
+}
+
+// Length of the hash suffix that keeps `a/IMG_0001.jpg` and `b/IMG_0001.jpg` apart
+const SHORT_HASH_LENGTH: usize = 8;
+
+// `<source stem>-<short hash>.<ext>`, just the hash when the source is unknown
+fn output_name(hash: &str, index: &CacheIndex, format: OutputFormat) -> String {
+    let stem = index
+        .entry(hash)
+        .ok()
+        .flatten()
+        .and_then(|entry| entry["source_path"].as_str().and_then(|path| Path::new(path).file_stem()?.to_str().map(str::to_string)));
+
+    // Hashes are validated ASCII, so any byte offset is a character boundary
+    match stem {
+        Some(stem) => format!("{}-{}.{}", stem, &hash[hash.len().saturating_sub(SHORT_HASH_LENGTH)..], format.extension()),
+        None => format!("{}.{}", hash, format.extension()),
+    }
+}
+
+/// Exports every image in `hashes` into `output_dir` with `policy` applied, one entry per image.
+fn strip(
+    hashes: &[String],
+    output_dir: &Path,
+    format: OutputFormat,
+    quality: u8,
+    policy: &MetadataPolicy,
+    dry_run: bool,
+    index: &CacheIndex,
+) -> Vec<ImportEntry> {
+    hashes
+        .iter()
+        .map(|hash| {
+            let output_path = output_dir.join(output_name(hash, index, format));
+
+            match export_with_policy(hash, &output_path, format, quality, policy, dry_run, index) {
+                Ok(report) => ImportEntry::Ok(json!({ "hash": hash, "report": report })),
+                Err(e) => ImportEntry::Error(ImportFailure::new(hash, &ImportError::WriteMetadata(e))),
+            }
+        })
+        .collect()
+}
+
+#[tauri::command]
+pub async fn apply_metadata_policy(
+    hashes: Vec<String>,
+    output_dir: String,
+    policy: MetadataPolicy,
+    format: Option<OutputFormat>,
+    quality: Option<u8>,
+    dry_run: Option<bool>,
+    index: State<'_, CacheIndex>,
+) -> Result<Vec<ImportEntry>, String> {
+    cache::validate_hashes(&hashes)?;
+    let index = index.inner().clone();
+    let output_dir = PathBuf::from(output_dir);
+    let format = format.unwrap_or(OutputFormat::Jpeg);
+    let quality = quality.unwrap_or(formats::DEFAULT_QUALITY);
+    let dry_run = dry_run.unwrap_or(false);
+
+    if !dry_run {
+        std::fs::create_dir_all(&output_dir).map_err(|e| format!("Failed to create {:?}: {}", output_dir, e))?;
+    }
+
+    tokio::task::spawn_blocking(move || strip(&hashes, &output_dir, format, quality, &policy, dry_run, &index))
+        .await
+        .map_err(|e| format!("Metadata policy task failed: {}", e))
+}
@@ -1 +1,23 @@
+pub mod benchmark;
+pub mod cache;
+pub mod cache_index;
+pub mod error;
+pub mod export;
+pub mod formats;
+pub mod icc;
+pub mod jobs;
 pub mod lowres_rs;
+pub mod metadata;
+pub mod metadata_edit;
+pub mod metadata_policy;
+pub mod pages;
+pub mod pool;
+pub mod print;
+pub mod python;
+pub mod raw;
+pub mod resample;
+pub mod resize;
+pub mod resolution;
+pub mod svg;
+pub mod tiff_writer;
+pub mod tiles;
@@ -0,0 +1,224 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::error::{DecodingError, UnsupportedError, UnsupportedErrorKind};
+use image::{AnimationDecoder, DynamicImage, Frames, ImageBuffer, ImageDecoder, ImageError, ImageFormat};
+use tiff::decoder::{Decoder, DecodingResult, Limits};
+use tiff::tags::Tag;
+use tiff::TiffError;
+
+use crate::image::error::ImportError;
+
+// NewSubfileType bit marking a reduced-resolution copy of another page
+const REDUCED_RESOLUTION: u32 = 1;
+
+// Frames are composited as 8-bit RGBA onto a canvas the decoder keeps next to each frame it returns
+const FRAME_BYTES_PER_PIXEL: u64 = 4 + 4;
+
+/// One page of a multi-page document or one frame of an animation.
+pub struct Page {
+    pub image: DynamicImage,
+    /// How long the frame stays on screen, `None` for document pages
+    pub delay_ms: Option<u32>,
+}
+
+/// What walking the pages of a file found.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PageSequence {
+    pub page_count: usize,
+    /// Delay of the first frame, the regular decoder does not report it
+    pub first_delay_ms: Option<u32>,
+    pub animated: bool,
+}
+
+/// Formats that can hold more than one page or frame.
+pub const PAGED_EXTENSIONS: [&str; 6] = ["gif", "webp", "png", "apng", "tif", "tiff"];
+
+pub fn is_paged(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| PAGED_EXTENSIONS.iter().any(|known| extension.eq_ignore_ascii_case(known)))
+}
+
+/// Name later pages are cached under inside the directory named after the parent hash.
+pub fn page_name(index: usize) -> String {
+    format!("page-{}", index)
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, ImportError> {
+    File::open(path).map(BufReader::new).map_err(ImportError::Open)
+}
+
+fn tiff_error(error: TiffError) -> ImportError {
+    ImportError::Decode(match error {
+        TiffError::IoError(e) => ImageError::IoError(e),
+        e => ImageError::Decoding(DecodingError::new(ImageFormat::Tiff.into(), e)),
+    })
+}
+
+fn unsupported_color(color: tiff::ColorType) -> ImportError {
+    ImportError::Decode(ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+        ImageFormat::Tiff.into(),
+        UnsupportedErrorKind::GenericFeature(format!("TIFF colour type {:?}", color)),
+    )))
+}
+
+/// Decodes every page after the first of `path`, handing each to `on_page` with its index
+/// before the next one is decoded, so only one page is held at a time. The first page is
+/// what the regular decoder returns and is only counted. Files that are not paged, or hold
+/// a single page, report a count of 1 without decoding anything. `reserve` is called with
+/// the width, height and bytes per pixel of each page before it is decoded, so its memory
+/// can be reserved first.
+pub fn for_each_page(
+    path: &Path,
+    mut reserve: impl FnMut(u32, u32, u64) -> Result<(), ImportError>,
+    mut on_page: impl FnMut(usize, Page) -> Result<(), ImportError>,
+) -> Result<PageSequence, ImportError> {
+    let single = PageSequence { page_count: 1, ..Default::default() };
+
+    let Some(format) = ImageFormat::from_path(path).ok().filter(|_| is_paged(path)) else {
+        return Ok(single);
+    };
+
+    match format {
+        ImageFormat::Tiff => for_each_tiff_page(path, reserve, on_page),
+        ImageFormat::Gif => {
+            let decoder = GifDecoder::new(open(path)?).map_err(ImportError::Decode)?;
+            let (width, height) = decoder.dimensions();
+            for_each_frame(decoder.into_frames(), || reserve(width, height, FRAME_BYTES_PER_PIXEL), &mut on_page)
+        }
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(open(path)?).map_err(ImportError::Decode)?;
+            if !decoder.has_animation() {
+                return Ok(single);
+            }
+
+            let (width, height) = decoder.dimensions();
+            for_each_frame(decoder.into_frames(), || reserve(width, height, FRAME_BYTES_PER_PIXEL), &mut on_page)
+        }
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(open(path)?).map_err(ImportError::Decode)?;
+            if !decoder.is_apng().map_err(ImportError::Decode)? {
+                return Ok(single);
+            }
+
+            let (width, height) = decoder.dimensions();
+            let frames = decoder.apng().map_err(ImportError::Decode)?.into_frames();
+            for_each_frame(frames, || reserve(width, height, FRAME_BYTES_PER_PIXEL), &mut on_page)
+        }
+        _ => Ok(single),
+    }
+}
+
+// Frames come out composited onto the full canvas, ready to show on their own. The first
+// frame is decoded again here to get to the ones after it, so it is reserved like the rest
+fn for_each_frame(
+    mut frames: Frames,
+    mut reserve: impl FnMut() -> Result<(), ImportError>,
+    on_page: &mut impl FnMut(usize, Page) -> Result<(), ImportError>,
+) -> Result<PageSequence, ImportError> {
+    let mut sequence = PageSequence { animated: true, ..Default::default() };
+
+    for index in 0.. {
+        reserve()?;
+        let Some(frame) = frames.next() else {
+            break;
+        };
+
+        let frame = frame.map_err(ImportError::Decode)?;
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+        let delay_ms = numerator / denominator.max(1);
+
+        sequence.page_count += 1;
+        if index == 0 {
+            sequence.first_delay_ms = Some(delay_ms);
+            continue;
+        }
+
+        on_page(index, Page {
+            image: DynamicImage::ImageRgba8(frame.into_buffer()),
+            delay_ms: Some(delay_ms),
+        })?;
+    }
+
+    Ok(sequence)
+}
+
+fn for_each_tiff_page(
+    path: &Path,
+    mut reserve: impl FnMut(u32, u32, u64) -> Result<(), ImportError>,
+    mut on_page: impl FnMut(usize, Page) -> Result<(), ImportError>,
+) -> Result<PageSequence, ImportError> {
+    let mut decoder = Decoder::new(open(path)?).map_err(tiff_error)?.with_limits(Limits::unlimited());
+    let mut sequence = PageSequence { page_count: 1, ..Default::default() };
+
+    while decoder.more_images() {
+        decoder.next_image().map_err(tiff_error)?;
+
+        // Thumbnails stored next to a page are not pages of their own
+        let subfile_type = decoder.find_tag_unsigned::<u32>(Tag::NewSubfileType).map_err(tiff_error)?;
+        if subfile_type.is_some_and(|subfile_type| subfile_type & REDUCED_RESOLUTION != 0) {
+            continue;
+        }
+
+        let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+        reserve(width, height, tiff_bytes_per_pixel(decoder.colortype().map_err(tiff_error)?))?;
+
+        let image = decode_tiff_page(&mut decoder)?;
+        on_page(sequence.page_count, Page { image, delay_ms: None })?;
+        sequence.page_count += 1;
+    }
+
+    Ok(sequence)
+}
+
+// Decoded samples plus the RGB copy CMYK pages are converted to
+fn tiff_bytes_per_pixel(color: tiff::ColorType) -> u64 {
+    let (samples, bits) = match color {
+        tiff::ColorType::Gray(bits) => (1, bits),
+        tiff::ColorType::GrayA(bits) => (2, bits),
+        tiff::ColorType::RGB(bits) => (3, bits),
+        tiff::ColorType::RGBA(bits) => (4, bits),
+        tiff::ColorType::CMYK(bits) => (4 + 3, bits),
+        // Rejected once decoded, but the samples are read first
+        _ => (4, 16),
+    };
+
+    samples * (bits as u64).div_ceil(8)
+}
+
+// Supports the same colour types as the decoder in `image`, which reads the first page
+fn decode_tiff_page(decoder: &mut Decoder<BufReader<File>>) -> Result<DynamicImage, ImportError> {
+    let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+    let color = decoder.colortype().map_err(tiff_error)?;
+    let data = decoder.read_image().map_err(tiff_error)?;
+
+    let image = match (color, data) {
+        (tiff::ColorType::Gray(8), DecodingResult::U8(buffer)) => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageLuma8),
+        (tiff::ColorType::Gray(16), DecodingResult::U16(buffer)) => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageLuma16),
+        (tiff::ColorType::GrayA(8), DecodingResult::U8(buffer)) => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageLumaA8),
+        (tiff::ColorType::GrayA(16), DecodingResult::U16(buffer)) => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageLumaA16),
+        (tiff::ColorType::RGB(8), DecodingResult::U8(buffer)) => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageRgb8),
+        (tiff::ColorType::RGB(16), DecodingResult::U16(buffer)) => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageRgb16),
+        (tiff::ColorType::RGBA(8), DecodingResult::U8(buffer)) => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageRgba8),
+        (tiff::ColorType::RGBA(16), DecodingResult::U16(buffer)) => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageRgba16),
+        (tiff::ColorType::CMYK(8), DecodingResult::U8(buffer)) => {
+            let rgb = buffer
+                .chunks_exact(4)
+                .flat_map(|cmyk| {
+                    let black = 255 - cmyk[3] as u16;
+                    [0, 1, 2].map(|channel| ((255 - cmyk[channel] as u16) * black / 255) as u8)
+                })
+                .collect();
+
+            ImageBuffer::from_raw(width, height, rgb).map(DynamicImage::ImageRgb8)
+        }
+        (color, _) => return Err(unsupported_color(color)),
+    };
+
+    image.ok_or_else(|| tiff_error(TiffError::LimitsExceeded))
+}
@@ -0,0 +1,178 @@
+use std::collections::HashSet;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use image::ImageDecoder;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::image::jobs::CancellationToken;
+
+// 8-bit RGBA preview copy plus the largest scaled copy made from it
+const PREVIEW_BYTES_PER_PIXEL: u64 = 8;
+
+// Fewest bits compressed RAW formats store a sensor pixel in, so the pixels a file could hold
+// are never underestimated from its size
+const RAW_MIN_BITS_PER_PIXEL: u64 = 6;
+
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub fn default_worker_count() -> usize {
+    std::thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(1)
+}
+
+/// Rough peak usage while a file is in flight, from its header alone: the decoded
+/// source in its own colour type plus the 8-bit copies previews and tiles are made from.
+pub fn estimate_memory(decoder: &impl ImageDecoder) -> u64 {
+    let (width, height) = decoder.dimensions();
+    decoder.total_bytes() + width as u64 * height as u64 * PREVIEW_BYTES_PER_PIXEL
+}
+
+/// Same estimate for an SVG rendered at `width` x `height`: the premultiplied pixmap,
+/// the straight alpha copy made from it and the preview copies.
+pub fn estimate_raster_memory(width: u32, height: u32) -> u64 {
+    width as u64 * height as u64 * (4 + 4 + PREVIEW_BYTES_PER_PIXEL)
+}
+
+/// Same estimate for a decoder that only reports its size and sample layout.
+pub fn estimate_decoded_memory(width: u32, height: u32, bytes_per_pixel: u64) -> u64 {
+    width as u64 * height as u64 * (bytes_per_pixel + PREVIEW_BYTES_PER_PIXEL)
+}
+
+/// Same estimate for a RAW file of `file_size` bytes, made before it is decoded since only
+/// decoding tells its size: the file read into memory and rawloader's copy of it, plus what
+/// [`estimate_raw_memory`] counts for as many pixels as the file could hold.
+pub fn estimate_raw_file_memory(file_size: u64) -> u64 {
+    let pixels = file_size.saturating_mul(8) / RAW_MIN_BITS_PER_PIXEL;
+    file_size.saturating_mul(2).saturating_add(pixels.saturating_mul(2 + 4 + 6 + PREVIEW_BYTES_PER_PIXEL))
+}
+
+/// Same estimate for a decoded RAW file of `file_size` bytes whose sensor holds `samples`
+/// values and develops to `width` x `height`: the file still held for its preview,
+/// rawloader's 16-bit samples and our float copy of them, the 16-bit RGB result and the
+/// preview copies.
+pub fn estimate_raw_memory(file_size: u64, samples: u64, width: u32, height: u32) -> u64 {
+    file_size + samples * (2 + 4) + width as u64 * height as u64 * (6 + PREVIEW_BYTES_PER_PIXEL)
+}
+
+pub fn build_pool(workers: usize) -> Result<ThreadPool, String> {
+    ThreadPoolBuilder::new()
+        .num_threads(workers.max(1))
+        .thread_name(|index| format!("image-import-{}", index))
+        .build()
+        .map_err(|e| format!("Failed to start import workers: {}", e))
+}
+
+/// Caps how many bytes of decoded pixels the workers may hold at once.
+pub struct MemoryBudget {
+    limit: u64,
+    in_use: Mutex<u64>,
+    released: Condvar,
+}
+
+/// Bytes held against a [`MemoryBudget`], handed back when dropped.
+pub struct MemoryReservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit: limit.max(1),
+            in_use: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Blocks until `bytes` fit in the budget. A request larger than the whole budget is
+    /// clamped so it still runs, just never alongside anything else.
+    /// Returns `None` if the job is cancelled while waiting.
+    pub fn acquire(&self, bytes: u64, cancel: &CancellationToken) -> Option<MemoryReservation<'_>> {
+        let bytes = bytes.min(self.limit);
+        let mut in_use = self.in_use.lock().unwrap();
+
+        // `in_use` never exceeds `limit`, so this cannot overflow even for a budget of `u64::MAX`
+        while bytes > self.limit - *in_use {
+            if cancel.is_cancelled() {
+                return None;
+            }
+
+            in_use = self.released.wait_timeout(in_use, CANCEL_POLL_INTERVAL).unwrap().0;
+        }
+
+        *in_use += bytes;
+
+        Some(MemoryReservation { budget: self, bytes })
+    }
+
+    /// Same as [`acquire`](Self::acquire) for a file that already holds `held`. The two are
+    /// clamped to the whole budget together, so a file never ends up waiting on itself.
+    pub fn acquire_more(&self, bytes: u64, held: &MemoryReservation, cancel: &CancellationToken) -> Option<MemoryReservation<'_>> {
+        self.acquire(bytes.min(self.limit - held.bytes), cancel)
+    }
+}
+
+impl MemoryReservation<'_> {
+    /// Hands back whatever is held above `bytes`, once a file turns out to need less than
+    /// it was estimated at.
+    pub fn shrink_to(&mut self, bytes: u64) {
+        if bytes >= self.bytes {
+            return;
+        }
+
+        *self.budget.in_use.lock().unwrap() -= self.bytes - bytes;
+        self.bytes = bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+impl Drop for MemoryReservation<'_> {
+    fn drop(&mut self) {
+        *self.budget.in_use.lock().unwrap() -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+/// Hashes a worker is caching right now. A second file with the same pixels waits for the
+/// first to finish instead of writing the same copies next to it, and is then answered from
+/// the cache.
+#[derive(Default)]
+pub struct HashClaims {
+    claimed: Mutex<HashSet<String>>,
+    released: Condvar,
+}
+
+/// A hash held in [`HashClaims`], released when dropped.
+pub struct HashClaim<'a> {
+    claims: &'a HashClaims,
+    hash: String,
+}
+
+impl HashClaims {
+    /// Blocks until no other worker holds `hash`, then takes it.
+    /// Returns `None` if the job is cancelled while waiting.
+    pub fn claim(&self, hash: &str, cancel: &CancellationToken) -> Option<HashClaim<'_>> {
+        let mut claimed = self.claimed.lock().unwrap();
+
+        while claimed.contains(hash) {
+            if cancel.is_cancelled() {
+                return None;
+            }
+
+            claimed = self.released.wait_timeout(claimed, CANCEL_POLL_INTERVAL).unwrap().0;
+        }
+
+        claimed.insert(hash.to_string());
+
+        Some(HashClaim { claims: self, hash: hash.to_string() })
+    }
+}
+
+impl Drop for HashClaim<'_> {
+    fn drop(&mut self) {
+        self.claims.claimed.lock().unwrap().remove(&self.hash);
+        self.claims.released.notify_all();
+    }
+}
@@ -0,0 +1,345 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::image::cache;
+use crate::image::cache_index::CacheIndex;
+use crate::image::formats::{self, OutputFormat};
+use crate::image::metadata_policy::{self, MetadataPolicy, PolicyReport};
+use crate::image::resample::ResampleFilter;
+use crate::image::resize;
+use crate::image::resolution::{self, Resolution, ResolutionSource, ResolutionUnit};
+
+/// How far a print size may be off the image's aspect ratio before it counts as a different shape.
+const ASPECT_TOLERANCE: f64 = 0.01;
+
+/// What to print an image at. Sizes are in `unit`, giving only one of them keeps the aspect ratio.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PrintOptions {
+    /// The image's own resolution when left out
+    pub dpi: Option<f64>,
+    pub width: Option<f64>,
+    pub height: Option<f64>,
+    #[serde(default)]
+    pub unit: ResolutionUnit,
+    /// Scale the pixels to fit the new size, otherwise only the resolution tags change
+    #[serde(default)]
+    pub resample: bool,
+}
+
+/// Size of an image on paper.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct PhysicalSize {
+    pub width: f64,
+    pub height: f64,
+    pub unit: ResolutionUnit,
+}
+
+impl PhysicalSize {
+    fn of((width, height): (u32, u32), resolution: &Resolution, unit: ResolutionUnit) -> Self {
+        Self {
+            width: width as f64 / resolution.x_dpi * unit.per_inch(),
+            height: height as f64 / resolution.y_dpi * unit.per_inch(),
+            unit,
+        }
+    }
+}
+
+/// Pixels, resolution and the print size they make.
+#[derive(Debug, Clone, Serialize)]
+pub struct PrintLayout {
+    pub width: u32,
+    pub height: u32,
+    pub resolution: Resolution,
+    pub size: PhysicalSize,
+}
+
+impl PrintLayout {
+    fn new(pixels: (u32, u32), resolution: Resolution, unit: ResolutionUnit) -> Self {
+        Self {
+            width: pixels.0,
+            height: pixels.1,
+            resolution,
+            size: PhysicalSize::of(pixels, &resolution, unit),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PrintReport {
+    pub before: PrintLayout,
+    pub after: PrintLayout,
+    pub resampled: bool,
+    /// Only set when a file was written
+    pub path: Option<String>,
+    pub metadata: Option<PolicyReport>,
+}
+
+/// Print width and height in inches asked for by `options`, `None` when it gives no size.
+fn requested_size(options: &PrintOptions, (width, height): (u32, u32)) -> Result<Option<(f64, f64)>, String> {
+    let aspect = height as f64 / width as f64;
+    let to_inches = |value: f64| value / options.unit.per_inch();
+
+    let size = match (options.width, options.height) {
+        (Some(print_width), Some(print_height)) => {
+            if ((print_height / print_width) / aspect - 1.0).abs() > ASPECT_TOLERANCE {
+                return Err(format!(
+                    "{} x {} does not have the aspect ratio of a {}x{} image",
+                    print_width, print_height, width, height
+                ));
+            }
+            Some((to_inches(print_width), to_inches(print_height)))
+        }
+        (Some(print_width), None) => Some((to_inches(print_width), to_inches(print_width) * aspect)),
+        (None, Some(print_height)) => Some((to_inches(print_height) / aspect, to_inches(print_height))),
+        (None, None) => None,
+    };
+
+    match size {
+        Some((width, height)) if !(width.is_finite() && height.is_finite() && width > 0.0 && height > 0.0) => {
+            Err("The print size has to be larger than zero".to_string())
+        }
+        size => Ok(size),
+    }
+}
+
+/// Works out the pixels and resolution an image of `pixels` at `current` ends up with.
+/// Without resampling the pixels stay, so a print size and a DPI cannot both be asked for.
+pub fn plan(pixels: (u32, u32), current: &Resolution, options: &PrintOptions) -> Result<PrintLayout, String> {
+    if options.dpi.is_some_and(|dpi| !(dpi.is_finite() && dpi > 0.0)) {
+        return Err("The DPI has to be larger than zero".to_string());
+    }
+
+    let size = requested_size(options, pixels)?;
+    let dpi = options.dpi.unwrap_or((current.x_dpi + current.y_dpi) / 2.0);
+
+    let (new_pixels, (x_dpi, y_dpi)) = match (options.resample, size, options.dpi) {
+        (false, Some(_), Some(_)) => {
+            return Err("Without resampling either the DPI or the print size can be set, not both".to_string());
+        }
+        (false, Some((width, _)), None) => {
+            let dpi = pixels.0 as f64 / width;
+            (pixels, (dpi, dpi))
+        }
+        (false, None, Some(dpi)) => (pixels, (dpi, dpi)),
+        (false, None, None) => (pixels, (current.x_dpi, current.y_dpi)),
+        (true, size, _) => {
+            let (width, height) = size.unwrap_or((pixels.0 as f64 / current.x_dpi, pixels.1 as f64 / current.y_dpi));
+            let to_pixels = |inches: f64| ((inches * dpi).round() as u32).max(1);
+
+            ((to_pixels(width), to_pixels(height)), (dpi, dpi))
+        }
+    };
+
+    let resolution = Resolution::new(x_dpi, y_dpi, options.unit, ResolutionSource::Requested);
+    Ok(PrintLayout::new(new_pixels, resolution, options.unit))
+}
+
+/// Plans `options` for the cached image `hash` and, when `output` is given, exports it
+/// with the new resolution written into the file. Resampled copies are scaled with Lanczos3
+/// like the previews are, but keep the colour type and bit depth of the high-res copy.
+pub fn print_image(
+    hash: &str,
+    options: &PrintOptions,
+    output: Option<(&Path, OutputFormat, u8)>,
+    policy: &MetadataPolicy,
+    index: &CacheIndex,
+) -> Result<PrintReport, String> {
+    let entry = index.entry(hash)?.ok_or_else(|| format!("{} is not in the cache", hash))?;
+
+    let dimensions = &entry["dimensions"]["highres"];
+    let pixels = match (dimensions["width"].as_u64(), dimensions["height"].as_u64()) {
+        (Some(width), Some(height)) => (width as u32, height as u32),
+        _ => return Err(format!("The cache entry of {} has no dimensions", hash)),
+    };
+    let current = serde_json::from_value::<Resolution>(entry["resolution"].clone()).unwrap_or_else(|_| Resolution::fallback());
+
+    let before = PrintLayout::new(pixels, current, options.unit);
+    let after = plan(pixels, &current, options)?;
+    let resampled = (after.width, after.height) != pixels;
+
+    let mut report = PrintReport {
+        before,
+        after,
+        resampled,
+        path: None,
+        metadata: None,
+    };
+
+    let Some((output_path, format, quality)) = output else {
+        return Ok(report);
+    };
+
+    // Checked before anything is written, the export would otherwise be left without its resolution
+    if !format.holds_resolution() {
+        return Err(format!(
+            "A print size cannot be stored in {} files, export as PNG or JPEG instead",
+            format.extension().to_uppercase()
+        ));
+    }
+
+    index.record_access(hash);
+    let mut image = cache::open_highres_srgb(hash)?;
+    if resampled {
+        image = resize::resize_image(&image, report.after.width, report.after.height, ResampleFilter::Lanczos3)?;
+    }
+
+    formats::write_image(&image, format, quality, output_path).map_err(|e| format!("Failed to export image: {}", e))?;
+
+    let source_path = entry["source_path"].as_str().map(PathBuf::from);
+    report.metadata = Some(metadata_policy::apply_policy(source_path.as_deref(), output_path, policy, false));
+
+    // The policy never copies the source's resolution, so this is the only one in the file
+    resolution::write_resolution(output_path, &report.after.resolution)?;
+    report.path = Some(output_path.to_string_lossy().to_string());
+
+    Ok(report)
+}
+
+/// Computes the print size of a cached image at a new DPI or size. With a `path` the image is
+/// also exported there with the new resolution, resampled first if `options.resample` is set.
+/// Only PNG and JPEG can hold the resolution. Without a `path` nothing is written.
+#[tauri::command]
+pub async fn set_print_size(
+    hash: String,
+    options: PrintOptions,
+    path: Option<String>,
+    format: Option<OutputFormat>,
+    quality: Option<u8>,
+    metadata_policy: Option<MetadataPolicy>,
+    index: State<'_, CacheIndex>,
+) -> Result<PrintReport, String> {
+    cache::validate_hash(&hash)?;
+    let output_path = path.map(PathBuf::from);
+    let format = match &output_path {
+        Some(output_path) => Some(
+            format
+                .or_else(|| OutputFormat::from_path(output_path))
+                .ok_or_else(|| format!("Cannot tell the export format from {:?}", output_path))?,
+        ),
+        None => None,
+    };
+    let quality = quality.unwrap_or(formats::DEFAULT_QUALITY);
+    let policy = metadata_policy.unwrap_or_default();
+    let index = index.inner().clone();
+
+    tokio::task::spawn_blocking(move || {
+        let output = output_path.as_deref().zip(format).map(|(path, format)| (path, format, quality));
+        print_image(&hash, &options, output, &policy, &index)
+    })
+    .await
+    .map_err(|e| format!("Print size task failed: {}", e))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolution(dpi: f64) -> Resolution {
+        Resolution::new(dpi, dpi, ResolutionUnit::Inch, ResolutionSource::Png)
+    }
+
+    fn options(dpi: Option<f64>, width: Option<f64>, height: Option<f64>, resample: bool) -> PrintOptions {
+        PrintOptions {
+            dpi,
+            width,
+            height,
+            unit: ResolutionUnit::Inch,
+            resample,
+        }
+    }
+
+    #[test]
+    fn new_dpi_without_resampling_keeps_the_pixels() {
+        let layout = plan((3000, 2000), &resolution(72.0), &options(Some(300.0), None, None, false)).unwrap();
+
+        assert_eq!((layout.width, layout.height), (3000, 2000));
+        assert_eq!(layout.resolution.dpi(), 300);
+        assert_eq!(layout.resolution.source, ResolutionSource::Requested);
+        assert!((layout.size.width - 10.0).abs() < 1e-9);
+        assert!((layout.size.height - 2000.0 / 300.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn print_size_without_resampling_changes_the_dpi() {
+        let layout = plan((3000, 2000), &resolution(72.0), &options(None, Some(10.0), None, false)).unwrap();
+
+        assert_eq!((layout.width, layout.height), (3000, 2000));
+        assert_eq!(layout.resolution.dpi(), 300);
+    }
+
+    #[test]
+    fn print_size_in_centimeters() {
+        let options = PrintOptions {
+            unit: ResolutionUnit::Centimeter,
+            ..options(None, None, Some(2.54), false)
+        };
+        let layout = plan((3000, 2000), &resolution(72.0), &options).unwrap();
+
+        assert_eq!(layout.resolution.dpi(), 2000);
+        assert_eq!(layout.resolution.unit, ResolutionUnit::Centimeter);
+        assert!((layout.size.height - 2.54).abs() < 1e-9);
+    }
+
+    #[test]
+    fn nothing_asked_for_keeps_everything() {
+        let current = Resolution::new(300.0, 150.0, ResolutionUnit::Inch, ResolutionSource::Tiff);
+        let layout = plan((3000, 2000), &current, &options(None, None, None, false)).unwrap();
+
+        assert_eq!((layout.width, layout.height), (3000, 2000));
+        assert_eq!((layout.resolution.x_dpi, layout.resolution.y_dpi), (300.0, 150.0));
+    }
+
+    #[test]
+    fn dpi_and_size_without_resampling_is_rejected() {
+        assert!(plan((3000, 2000), &resolution(72.0), &options(Some(300.0), Some(10.0), None, false)).is_err());
+    }
+
+    #[test]
+    fn resampling_to_a_size_at_a_dpi() {
+        let layout = plan((3000, 2000), &resolution(72.0), &options(Some(150.0), Some(4.0), None, true)).unwrap();
+
+        assert_eq!((layout.width, layout.height), (600, 400));
+        assert_eq!(layout.resolution.dpi(), 150);
+    }
+
+    #[test]
+    fn resampling_to_a_dpi_keeps_the_print_size() {
+        // 10 x 5 inches at 100 DPI
+        let layout = plan((1000, 500), &resolution(100.0), &options(Some(300.0), None, None, true)).unwrap();
+
+        assert_eq!((layout.width, layout.height), (3000, 1500));
+        assert!((layout.size.width - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn resampling_to_a_size_keeps_the_dpi() {
+        let layout = plan((1000, 500), &resolution(100.0), &options(None, None, Some(2.5), true)).unwrap();
+
+        assert_eq!((layout.width, layout.height), (500, 250));
+        assert_eq!(layout.resolution.dpi(), 100);
+    }
+
+    #[test]
+    fn resampling_never_goes_below_a_pixel() {
+        let layout = plan((1000, 10), &resolution(100.0), &options(Some(1.0), None, None, true)).unwrap();
+
+        assert_eq!((layout.width, layout.height), (10, 1));
+    }
+
+    #[test]
+    fn a_size_of_another_shape_is_rejected() {
+        assert!(plan((3000, 2000), &resolution(72.0), &options(None, Some(10.0), Some(10.0), true)).is_err());
+
+        // Within the tolerance it is the same shape
+        assert!(plan((3000, 2000), &resolution(72.0), &options(None, Some(10.0), Some(6.7), true)).is_ok());
+    }
+
+    #[test]
+    fn sizes_and_dpi_have_to_be_positive() {
+        assert!(plan((3000, 2000), &resolution(72.0), &options(Some(0.0), None, None, false)).is_err());
+        assert!(plan((3000, 2000), &resolution(72.0), &options(Some(f64::NAN), None, None, true)).is_err());
+        assert!(plan((3000, 2000), &resolution(72.0), &options(None, Some(-1.0), None, true)).is_err());
+    }
+}
@@ -0,0 +1,354 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tauri::path::BaseDirectory;
+use tauri::{ipc::Channel, AppHandle, Manager, State};
+use tokio::time::Instant;
+
+use crate::global::IMAGE_CACHE_DIR;
+use crate::image::cache::{self, CachePins};
+use crate::image::cache_index::CacheIndex;
+use crate::image::error::{display_filename, ImportEntry, ImportError, ImportFailure};
+use crate::image::jobs::{CancellationToken, JobRegistry};
+use crate::image::lowres_rs::{error_event, send_event};
+
+/// Version of the JSON-lines protocol spoken with `src-python/main.py`, bumped on breaking changes.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+const SCRIPT_PATH: &str = "src-python/main.py";
+
+/// Environment variable naming the interpreter to run the script with. It is only ever read
+/// from the environment the app was started in, never from the webview, which must not be
+/// able to choose what the backend executes.
+const INTERPRETER_VARIABLE: &str = "IMAGE_PYTHON_INTERPRETER";
+
+/// What the Python backend is asked to do with the files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PythonAction {
+    /// Cache the files like an import does and return their entries
+    Import,
+    /// Run a filter over the files and return where the filtered copies were written
+    Filter,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PythonOptions {
+    /// Handed to the script untouched, e.g. `preview_size` or a filter's `radius`
+    pub params: serde_json::Value,
+}
+
+/// One call into the Python backend.
+#[derive(Debug, Clone)]
+pub struct PythonJob {
+    pub action: PythonAction,
+    pub paths: Vec<String>,
+    /// Name of the filter to run, only used by [`PythonAction::Filter`]
+    pub filter: Option<String>,
+    pub options: PythonOptions,
+}
+
+/// The single line written to the script's stdin.
+#[derive(Debug, Clone, Serialize)]
+struct PythonRequest<'a> {
+    protocol: u32,
+    action: PythonAction,
+    paths: &'a [String],
+    cache_dir: &'a Path,
+    filter: Option<&'a str>,
+    params: &'a serde_json::Value,
+}
+
+/// One line of the script's stdout. Lines that are not one of these are printed as logs.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "event", content = "data", rename_all = "lowercase")]
+enum PythonMessage {
+    /// Same shape as the pipeline's own progress event, forwarded as is
+    Progress(serde_json::Value),
+    Error { path: String, message: String },
+    Result(serde_json::Value),
+}
+
+fn default_interpreter() -> PathBuf {
+    PathBuf::from(if cfg!(windows) { "python" } else { "python3" })
+}
+
+/// The interpreter set in [`INTERPRETER_VARIABLE`], which has to be the absolute path of an
+/// existing file, or `python3` (`python` on Windows) from `PATH` when it is not set.
+pub fn interpreter() -> Result<PathBuf, String> {
+    let Some(configured) = std::env::var_os(INTERPRETER_VARIABLE) else {
+        return Ok(default_interpreter());
+    };
+
+    let configured = PathBuf::from(configured);
+    if !configured.is_absolute() || !configured.is_file() {
+        return Err(format!("{} has to be the absolute path of a Python interpreter, not {:?}", INTERPRETER_VARIABLE, configured));
+    }
+
+    Ok(configured)
+}
+
+/// The bundled script, checked to be a file inside the app's resources.
+fn script_path(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    let not_found = |e: &dyn std::fmt::Display| format!("Failed to find the Python backend: {}", e);
+
+    let resource_dir = app_handle.path().resource_dir().map_err(|e| not_found(&e))?;
+    let script = app_handle
+        .path()
+        .resolve(SCRIPT_PATH, BaseDirectory::Resource)
+        .map_err(|e| not_found(&e))?;
+
+    // Canonical paths, so `..` or a symlink cannot lead out of the resources
+    let resource_dir = resource_dir.canonicalize().map_err(|e| not_found(&e))?;
+    let script = script.canonicalize().map_err(|e| not_found(&e))?;
+
+    if !script.starts_with(&resource_dir) || !script.is_file() {
+        return Err(format!("The Python backend {:?} is not a file in {:?}", script, resource_dir));
+    }
+
+    Ok(script)
+}
+
+fn spawn(interpreter: &Path, script: &Path, request: &PythonRequest) -> Result<Child, String> {
+    let mut child = Command::new(interpreter)
+        .arg(script)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to start {:?}: {}", interpreter, e))?;
+
+    // Closing stdin after the request tells the script there is nothing else coming
+    let mut stdin = child.stdin.take().ok_or("Python backend has no stdin")?;
+    let request = serde_json::to_string(request).map_err(|e| format!("Failed to serialize request: {}", e))?;
+    writeln!(stdin, "{}", request).map_err(|e| format!("Failed to send request to the Python backend: {}", e))?;
+
+    Ok(child)
+}
+
+fn handle_message(
+    line: &str,
+    action: PythonAction,
+    index: &CacheIndex,
+    results: &mut Vec<ImportEntry>,
+    on_event: &impl Fn(serde_json::Value),
+) {
+    let message = match serde_json::from_str::<PythonMessage>(line) {
+        Ok(message) => message,
+        Err(_) => {
+            println!("[python] {}", line);
+            return;
+        }
+    };
+
+    match message {
+        PythonMessage::Progress(data) => on_event(json!({ "event": "progress", "data": data })),
+        PythonMessage::Error { path, message } => {
+            let failure = ImportFailure::new(&path, &ImportError::Python(message));
+            on_event(error_event(&failure));
+            results.push(ImportEntry::Error(failure));
+        }
+        PythonMessage::Result(mut output) => {
+            if let Some(source_path) = output["source_path"].as_str().map(str::to_string) {
+                output["filename"] = json!(display_filename(&source_path));
+            }
+
+            // Imported files join the cache like any other, filtered copies hang off an entry already there
+            if action == PythonAction::Import {
+                if let Err(e) = index.record_entry(&mut output) {
+                    println!("Failed to record Python result in the cache index: {}", e);
+                }
+            }
+
+            results.push(ImportEntry::Ok(output));
+        }
+    }
+}
+
+/// Runs the bundled Python script over `paths` and collects one entry per file.
+/// Progress and error events the script prints are passed to `on_event` in the same shape
+/// `process_images` sends them, followed by a `complete` or `cancelled` event.
+/// Cancelling `cancel` kills the script, the entries received until then are returned.
+pub fn run_python(
+    job: &PythonJob,
+    script: &Path,
+    image_cache_dir: &Path,
+    index: &CacheIndex,
+    cancel: &CancellationToken,
+    on_event: impl Fn(serde_json::Value) + Sync,
+) -> Result<Vec<ImportEntry>, String> {
+    let start_time = Instant::now();
+    let interpreter = interpreter()?;
+    let request = PythonRequest {
+        protocol: PROTOCOL_VERSION,
+        action: job.action,
+        paths: &job.paths,
+        cache_dir: image_cache_dir,
+        filter: job.filter.as_deref(),
+        params: &job.options.params,
+    };
+
+    let mut child = spawn(&interpreter, script, &request)?;
+    let stdout = child.stdout.take().ok_or("Python backend has no stdout")?;
+    let mut stderr = child.stderr.take().ok_or("Python backend has no stderr")?;
+
+    let child = Mutex::new(child);
+    let finished = AtomicBool::new(false);
+    let mut results = Vec::new();
+
+    let errors = std::thread::scope(|scope| {
+        // Reading stdout blocks, so cancellation is watched from the side and kills the script
+        scope.spawn(|| {
+            while !finished.load(Ordering::SeqCst) {
+                if cancel.is_cancelled() {
+                    let _ = child.lock().unwrap().kill();
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        });
+
+        // Drained on its own thread so a chatty script cannot fill the pipe and stall
+        let errors = scope.spawn(move || {
+            let mut errors = String::new();
+            let _ = stderr.read_to_string(&mut errors);
+            errors
+        });
+
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+
+            if !line.trim().is_empty() {
+                handle_message(&line, job.action, index, &mut results, &on_event);
+            }
+        }
+
+        finished.store(true, Ordering::SeqCst);
+        errors.join().unwrap_or_default()
+    });
+
+    let status = child
+        .into_inner()
+        .unwrap()
+        .wait()
+        .map_err(|e| format!("Failed to wait for the Python backend: {}", e))?;
+
+    let time_taken = Instant::now().duration_since(start_time);
+
+    if cancel.is_cancelled() {
+        on_event(json!({
+            "event": "cancelled",
+            "data": {
+                "time_taken": format!("{:.2?}", time_taken),
+                "total_files": job.paths.len(),
+                "processed_files": results.len(),
+                "results": results
+            }
+        }));
+
+        return Ok(results);
+    }
+
+    if !status.success() {
+        return Err(format!("Python backend exited with {}: {}", status, errors.trim()));
+    }
+
+    let failed_files = results.iter().filter(|entry| matches!(entry, ImportEntry::Error(_))).count();
+
+    on_event(json!({
+        "event": "complete",
+        "data": {
+            "time_taken": format!("{:.2?}", time_taken),
+            "total_files": job.paths.len(),
+            "failed_files": failed_files
+        }
+    }));
+
+    Ok(results)
+}
+
+async fn dispatch(
+    job: PythonJob,
+    app_handle: AppHandle,
+    channel: Channel,
+    registry: State<'_, JobRegistry>,
+    index: State<'_, CacheIndex>,
+    pins: State<'_, CachePins>,
+) -> Result<Vec<ImportEntry>, String> {
+    let image_cache_dir = IMAGE_CACHE_DIR.lock().unwrap().clone();
+    let script = script_path(&app_handle)?;
+
+    let index = index.inner().clone();
+    let pins = pins.inner().clone();
+    let (job_id, cancel) = registry.register();
+    send_event(&channel, json!({
+        "event": "started",
+        "data": {
+            "job_id": job_id,
+            "total_files": job.paths.len()
+        }
+    }));
+
+    let results = tokio::task::spawn_blocking(move || {
+        let results = run_python(&job, &script, &image_cache_dir, &index, &cancel, |event| send_event(&channel, event))?;
+        cache::evict_after_import(&image_cache_dir, &index, &pins, &results, |event| send_event(&channel, event));
+
+        Ok(results)
+    })
+    .await;
+    registry.finish(job_id);
+
+    results.map_err(|e| format!("Python task failed: {}", e))?
+}
+
+#[tauri::command]
+pub async fn import_with_python(
+    paths: Vec<String>,
+    options: Option<PythonOptions>,
+    app_handle: AppHandle,
+    channel: Channel,
+    registry: State<'_, JobRegistry>,
+    index: State<'_, CacheIndex>,
+    pins: State<'_, CachePins>,
+) -> Result<Vec<ImportEntry>, String> {
+    let job = PythonJob {
+        action: PythonAction::Import,
+        paths,
+        filter: None,
+        options: options.unwrap_or_default(),
+    };
+
+    dispatch(job, app_handle, channel, registry, index, pins).await
+}
+
+// Every argument is either part of the request or state Tauri hands in
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn filter_with_python(
+    paths: Vec<String>,
+    filter: String,
+    options: Option<PythonOptions>,
+    app_handle: AppHandle,
+    channel: Channel,
+    registry: State<'_, JobRegistry>,
+    index: State<'_, CacheIndex>,
+    pins: State<'_, CachePins>,
+) -> Result<Vec<ImportEntry>, String> {
+    let job = PythonJob {
+        action: PythonAction::Filter,
+        paths,
+        filter: Some(filter),
+        options: options.unwrap_or_default(),
+    };
+
+    dispatch(job, app_handle, channel, registry, index, pins).await
+}
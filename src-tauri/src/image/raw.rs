@@ -0,0 +1,353 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use image::{DynamicImage, ImageBuffer, ImageFormat, Rgb};
+use rawloader::{RawImage, RawImageData};
+use rayon::prelude::*;
+
+use crate::image::error::ImportError;
+
+/// Camera RAW formats the import pipeline develops itself.
+pub const RAW_EXTENSIONS: [&str; 9] = ["cr2", "nef", "arw", "dng", "orf", "rw2", "raf", "pef", "srw"];
+
+// Linear sRGB (D65) to XYZ
+const SRGB_TO_XYZ: [[f32; 3]; 3] = [
+    [0.412453, 0.357580, 0.180423],
+    [0.212671, 0.715160, 0.072169],
+    [0.019334, 0.119193, 0.950227],
+];
+
+// How far the tone curve pulls towards an S-curve after gamma encoding
+const CONTRAST: f32 = 0.25;
+
+// TIFF tags that lead to embedded previews
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014a;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+const TAG_EXIF_IFD: u16 = 0x8769;
+
+pub fn is_raw(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| RAW_EXTENSIONS.iter().any(|raw| extension.eq_ignore_ascii_case(raw)))
+}
+
+/// Decodes the sensor data of a RAW file already read into memory.
+pub fn decode_raw(data: &[u8]) -> Result<RawImage, ImportError> {
+    rawloader::decode(&mut &data[..]).map_err(|e| ImportError::Raw(e.to_string()))
+}
+
+/// Number of sensor values `decode_raw` produced, however many there are per pixel.
+pub fn sample_count(raw: &RawImage) -> u64 {
+    raw.width as u64 * raw.height as u64 * raw.cpp as u64
+}
+
+/// Width and height of the developed image, after the sensor margins are cropped.
+pub fn developed_dimensions(raw: &RawImage) -> (u32, u32) {
+    let [top, right, bottom, left] = raw.crops;
+    let width = raw.width.saturating_sub(left + right).max(1);
+    let height = raw.height.saturating_sub(top + bottom).max(1);
+
+    (width as u32, height as u32)
+}
+
+/// Develops sensor data into a 16-bit sRGB image: black and white level scaling, the
+/// white balance the camera recorded, bilinear demosaicing, the camera colour matrix and
+/// a gamma plus gentle contrast tone curve.
+pub fn develop(raw: &RawImage) -> Result<DynamicImage, ImportError> {
+    let samples: Vec<f32> = match &raw.data {
+        RawImageData::Integer(data) => data.iter().map(|&sample| sample as f32).collect(),
+        RawImageData::Float(data) => data.clone(),
+    };
+    if samples.len() < raw.width * raw.height * raw.cpp {
+        return Err(ImportError::Raw("Sensor data is shorter than the image".to_string()));
+    }
+
+    let white_balance = white_balance(raw);
+    let cam_to_rgb = camera_to_srgb(raw);
+    let monochrome = raw.is_monochrome();
+
+    let [top, _, _, left] = raw.crops;
+    let (width, height) = developed_dimensions(raw);
+
+    let mut pixels = vec![0u16; width as usize * height as usize * 3];
+    pixels
+        .par_chunks_mut(width as usize * 3)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, pixel) in row.chunks_exact_mut(3).enumerate() {
+                let (sensor_row, sensor_col) = (y + top, x + left);
+
+                let camera = if monochrome {
+                    let value = normalize(raw, 0, samples[sensor_row * raw.width + sensor_col]);
+                    [value; 4]
+                } else if raw.cpp == 3 {
+                    let start = (sensor_row * raw.width + sensor_col) * 3;
+                    let mut camera = [0.0; 4];
+                    for (color, value) in camera.iter_mut().take(3).enumerate() {
+                        *value = normalize(raw, color, samples[start + color]) * white_balance[color];
+                    }
+                    camera
+                } else {
+                    demosaic(raw, &samples, &white_balance, sensor_row, sensor_col)
+                };
+
+                let rgb = if monochrome {
+                    [camera[1]; 3]
+                } else {
+                    let mut rgb = [0.0; 3];
+                    for (channel, matrix_row) in rgb.iter_mut().zip(cam_to_rgb.iter()) {
+                        *channel = matrix_row.iter().zip(camera.iter()).map(|(m, c)| m * c.min(1.0)).sum();
+                    }
+                    rgb
+                };
+
+                for (out, value) in pixel.iter_mut().zip(rgb) {
+                    *out = (tone_curve(value) * u16::MAX as f32).round() as u16;
+                }
+            }
+        });
+
+    let buffer = ImageBuffer::<Rgb<u16>, Vec<u16>>::from_raw(width, height, pixels)
+        .ok_or_else(|| ImportError::Raw("Developed pixels do not match the image size".to_string()))?;
+
+    Ok(DynamicImage::ImageRgb16(buffer))
+}
+
+fn normalize(raw: &RawImage, color: usize, sample: f32) -> f32 {
+    let black = raw.blacklevels[color] as f32;
+    let white = raw.whitelevels[color] as f32;
+    if white <= black {
+        return 0.0;
+    }
+
+    ((sample - black) / (white - black)).max(0.0)
+}
+
+// White balance relative to green, falling back to daylight when the camera recorded none
+fn white_balance(raw: &RawImage) -> [f32; 4] {
+    let recorded = raw.wb_coeffs;
+    let coefficients = if recorded[..3].iter().all(|c| c.is_finite() && *c > 0.0) {
+        recorded
+    } else {
+        raw.neutralwb()
+    };
+
+    let green = coefficients[1];
+    let mut balance = coefficients.map(|c| c / green);
+    // A second green (or emerald) filter without its own coefficient follows green
+    if !balance[3].is_finite() || balance[3] <= 0.0 {
+        balance[3] = 1.0;
+    }
+
+    balance
+}
+
+// Camera RGBE to linear sRGB, normalized so white-balanced camera white is sRGB white
+fn camera_to_srgb(raw: &RawImage) -> [[f32; 4]; 3] {
+    let mut cam_to_srgb = [[0.0; 3]; 4];
+    for (i, row) in cam_to_srgb.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| raw.xyz_to_cam[i][k] * SRGB_TO_XYZ[k][j]).sum();
+        }
+    }
+
+    let matrix = RawImage::normalized_pseudoinverse(cam_to_srgb);
+    if matrix.iter().flatten().all(|value| value.is_finite()) {
+        matrix
+    } else {
+        // Unknown colour matrix, pass the camera channels through unchanged
+        [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0]]
+    }
+}
+
+// Average of each colour over the nearest ring of pixels that has it
+fn demosaic(raw: &RawImage, samples: &[f32], white_balance: &[f32; 4], row: usize, col: usize) -> [f32; 4] {
+    let mut sums = [0.0; 4];
+    let mut counts = [0u32; 4];
+
+    for radius in 1..=2isize {
+        let found = counts;
+
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                // The outer ring only fills in colours the inner one did not have
+                if dy.abs() < radius && dx.abs() < radius && radius > 1 {
+                    continue;
+                }
+
+                let (y, x) = (row as isize + dy, col as isize + dx);
+                if y < 0 || x < 0 || y >= raw.height as isize || x >= raw.width as isize {
+                    continue;
+                }
+
+                let (y, x) = (y as usize, x as usize);
+                let color = raw.cfa.color_at(y, x);
+                if found[color] > 0 {
+                    continue;
+                }
+
+                sums[color] += normalize(raw, color, samples[y * raw.width + x]) * white_balance[color];
+                counts[color] += 1;
+            }
+        }
+
+        // Bayer patterns have every colour within one pixel, X-Trans sometimes needs two
+        if counts[..3].iter().all(|&count| count > 0) {
+            break;
+        }
+    }
+
+    let own_color = raw.cfa.color_at(row, col);
+    let own = normalize(raw, own_color, samples[row * raw.width + col]) * white_balance[own_color];
+
+    let mut camera = [0.0; 4];
+    for color in 0..4 {
+        camera[color] = if color == own_color {
+            own
+        } else if counts[color] > 0 {
+            sums[color] / counts[color] as f32
+        } else {
+            0.0
+        };
+    }
+
+    // Patterns without a separate fourth colour report both greens as green
+    if counts[3] == 0 && own_color != 3 {
+        camera[3] = camera[1];
+    }
+
+    camera
+}
+
+// sRGB gamma followed by a mild S-curve for some of the contrast a camera would add
+fn tone_curve(linear: f32) -> f32 {
+    let linear = linear.clamp(0.0, 1.0);
+    let encoded = if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+
+    let s_curve = encoded * encoded * (3.0 - 2.0 * encoded);
+    encoded + CONTRAST * (s_curve - encoded)
+}
+
+/// The largest JPEG preview the camera embedded in the file, if there is one that decodes.
+/// CR2, NEF, ARW, DNG and most other RAW formats are TIFF containers, so every image
+/// directory is walked for JPEG streams.
+pub fn extract_preview(data: &[u8]) -> Option<DynamicImage> {
+    let mut candidates = Vec::new();
+    let reader = TiffReader::new(data)?;
+    let mut visited = HashSet::new();
+    reader.collect_jpegs(reader.u32(4)? as usize, &mut visited, &mut candidates);
+
+    candidates.sort_by_key(|(_, length)| std::cmp::Reverse(*length));
+    candidates.into_iter().find_map(|(offset, length)| {
+        let stream = data.get(offset..offset.checked_add(length)?)?;
+        if !stream.starts_with(&[0xff, 0xd8]) {
+            return None;
+        }
+
+        // Lossless JPEG holding the sensor data itself fails here and the next candidate is tried
+        image::load_from_memory_with_format(stream, ImageFormat::Jpeg).ok()
+    })
+}
+
+struct TiffReader<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> TiffReader<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+
+        Some(Self { data, little_endian })
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    }
+
+    // Values of a SHORT or LONG entry, read from the entry itself or from where it points
+    fn values(&self, entry: usize) -> Vec<usize> {
+        let (Some(kind), Some(count)) = (self.u16(entry + 2), self.u32(entry + 4)) else {
+            return Vec::new();
+        };
+        let size = match kind {
+            3 => 2,
+            4 | 13 => 4,
+            _ => return Vec::new(),
+        };
+
+        let count = count.min(64) as usize;
+        let start = if size * count <= 4 {
+            entry + 8
+        } else {
+            match self.u32(entry + 8) {
+                Some(offset) => offset as usize,
+                None => return Vec::new(),
+            }
+        };
+
+        (0..count)
+            .filter_map(|i| match size {
+                2 => self.u16(start + i * 2).map(usize::from),
+                _ => self.u32(start + i * 4).map(|value| value as usize),
+            })
+            .collect()
+    }
+
+    fn collect_jpegs(&self, mut ifd: usize, visited: &mut HashSet<usize>, candidates: &mut Vec<(usize, usize)>) {
+        while ifd != 0 && visited.insert(ifd) {
+            let Some(entry_count) = self.u16(ifd) else {
+                return;
+            };
+
+            let (mut jpeg_offset, mut jpeg_length) = (None, None);
+            let (mut compression, mut strip_offsets, mut strip_lengths) = (None, Vec::new(), Vec::new());
+            let mut children = Vec::new();
+
+            for i in 0..entry_count as usize {
+                let entry = ifd + 2 + i * 12;
+                match self.u16(entry) {
+                    Some(TAG_JPEG_OFFSET) => jpeg_offset = self.values(entry).first().copied(),
+                    Some(TAG_JPEG_LENGTH) => jpeg_length = self.values(entry).first().copied(),
+                    Some(TAG_COMPRESSION) => compression = self.values(entry).first().copied(),
+                    Some(TAG_STRIP_OFFSETS) => strip_offsets = self.values(entry),
+                    Some(TAG_STRIP_BYTE_COUNTS) => strip_lengths = self.values(entry),
+                    Some(TAG_SUB_IFDS) | Some(TAG_EXIF_IFD) => children.extend(self.values(entry)),
+                    _ => {}
+                }
+            }
+
+            if let (Some(offset), Some(length)) = (jpeg_offset, jpeg_length) {
+                candidates.push((offset, length));
+            }
+            // Old-style and new-style JPEG compression stored as a single strip
+            if matches!(compression, Some(6) | Some(7)) && strip_offsets.len() == 1 && strip_lengths.len() == 1 {
+                candidates.push((strip_offsets[0], strip_lengths[0]));
+            }
+
+            for child in children {
+                self.collect_jpegs(child, visited, candidates);
+            }
+
+            ifd = self.u32(ifd + 2 + entry_count as usize * 12).unwrap_or(0) as usize;
+        }
+    }
+}
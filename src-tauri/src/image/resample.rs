@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+
+/// Filter previews are scaled down with. Lanczos3 is the sharpest, Nearest keeps hard
+/// pixel edges for pixel art, CatmullRom and Mitchell are faster at a small cost in detail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResampleFilter {
+    Nearest,
+    Box,
+    Bilinear,
+    Hamming,
+    CatmullRom,
+    Mitchell,
+    #[default]
+    Lanczos3,
+}
+
+impl ResampleFilter {
+    /// Same name the frontend sends, also used in cached file names.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResampleFilter::Nearest => "nearest",
+            ResampleFilter::Box => "box",
+            ResampleFilter::Bilinear => "bilinear",
+            ResampleFilter::Hamming => "hamming",
+            ResampleFilter::CatmullRom => "catmullrom",
+            ResampleFilter::Mitchell => "mitchell",
+            ResampleFilter::Lanczos3 => "lanczos3",
+        }
+    }
+}
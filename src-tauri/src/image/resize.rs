@@ -0,0 +1,177 @@
+use fast_image_resize as fr;
+use fimg::scale::traits::ScalingAlgorithm;
+use fimg::scale::{Bilinear, Box as BoxFilter, CatmullRom, Hamming, Lanczos3, Mitchell, Nearest};
+use fimg::{DynImage, Image};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+use crate::image::resample::ResampleFilter;
+
+/// Scales the 8-bit previews made during import. `pixels` holds `channels` interleaved
+/// samples per pixel with straight alpha. Implementations must leave `pixels` untouched,
+/// the same preview is scaled to several sizes one after another.
+pub trait Resizer: Send + Sync {
+    fn resize(
+        &self,
+        pixels: &[u8],
+        channels: usize,
+        source: (u32, u32),
+        target: (u32, u32),
+        filter: ResampleFilter,
+    ) -> Result<Box<[u8]>, String>;
+}
+
+/// Which [`Resizer`] an import scales its previews with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeBackend {
+    #[default]
+    Fimg,
+    FastImageResize,
+}
+
+impl ResizeBackend {
+    /// Same name the frontend sends, also used in cached file names.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResizeBackend::Fimg => "fimg",
+            ResizeBackend::FastImageResize => "fast_image_resize",
+        }
+    }
+
+    pub fn resizer(&self) -> &'static dyn Resizer {
+        match self {
+            ResizeBackend::Fimg => &FimgResizer,
+            ResizeBackend::FastImageResize => &FastImageResizer,
+        }
+    }
+}
+
+/// Scales `image` to `width` x `height` with `backend`, keeping its channel layout.
+pub fn resize<T: AsRef<[u8]>>(
+    image: &DynImage<T>,
+    width: u32,
+    height: u32,
+    filter: ResampleFilter,
+    backend: ResizeBackend,
+) -> Result<DynImage<Box<[u8]>>, String> {
+    if width == 0 || height == 0 {
+        return Err(format!("Cannot resize to {}x{}", width, height));
+    }
+
+    let source = (image.width(), image.height());
+    let channels = match image {
+        DynImage::Y(_) => 1,
+        DynImage::Ya(_) => 2,
+        DynImage::Rgb(_) => 3,
+        DynImage::Rgba(_) => 4,
+    };
+
+    let pixels = backend.resizer().resize(image.bytes(), channels, source, (width, height), filter)?;
+
+    Ok(match image {
+        DynImage::Y(_) => DynImage::Y(Image::build(width, height).buf(pixels)),
+        DynImage::Ya(_) => DynImage::Ya(Image::build(width, height).buf(pixels)),
+        DynImage::Rgb(_) => DynImage::Rgb(Image::build(width, height).buf(pixels)),
+        DynImage::Rgba(_) => DynImage::Rgba(Image::build(width, height).buf(pixels)),
+    })
+}
+
+pub struct FimgResizer;
+
+// fimg premultiplies alpha in place, so images with alpha are scaled from a copy
+fn fimg_scale<A: ScalingAlgorithm>(pixels: &[u8], channels: usize, (width, height): (u32, u32), (target_width, target_height): (u32, u32)) -> Box<[u8]> {
+    match channels {
+        1 => Image::<_, 1>::build(width, height).buf(pixels).scale::<A>(target_width, target_height).take_buffer(),
+        2 => Image::<_, 2>::build(width, height).buf(pixels.to_vec()).scale::<A>(target_width, target_height).take_buffer(),
+        3 => Image::<_, 3>::build(width, height).buf(pixels).scale::<A>(target_width, target_height).take_buffer(),
+        _ => Image::<_, 4>::build(width, height).buf(pixels.to_vec()).scale::<A>(target_width, target_height).take_buffer(),
+    }
+}
+
+impl Resizer for FimgResizer {
+    fn resize(
+        &self,
+        pixels: &[u8],
+        channels: usize,
+        source: (u32, u32),
+        target: (u32, u32),
+        filter: ResampleFilter,
+    ) -> Result<Box<[u8]>, String> {
+        Ok(match filter {
+            ResampleFilter::Nearest => fimg_scale::<Nearest>(pixels, channels, source, target),
+            ResampleFilter::Box => fimg_scale::<BoxFilter>(pixels, channels, source, target),
+            ResampleFilter::Bilinear => fimg_scale::<Bilinear>(pixels, channels, source, target),
+            ResampleFilter::Hamming => fimg_scale::<Hamming>(pixels, channels, source, target),
+            ResampleFilter::CatmullRom => fimg_scale::<CatmullRom>(pixels, channels, source, target),
+            ResampleFilter::Mitchell => fimg_scale::<Mitchell>(pixels, channels, source, target),
+            ResampleFilter::Lanczos3 => fimg_scale::<Lanczos3>(pixels, channels, source, target),
+        })
+    }
+}
+
+/// Scales a full-resolution image with fast_image_resize in its own colour type and bit
+/// depth, for exports that have to keep what the 8-bit previews drop. Floating-point images
+/// come back as 16-bit, the deepest fast_image_resize takes from `image`.
+pub fn resize_image(image: &DynamicImage, width: u32, height: u32, filter: ResampleFilter) -> Result<DynamicImage, String> {
+    if width == 0 || height == 0 {
+        return Err(format!("Cannot resize to {}x{}", width, height));
+    }
+
+    let converted = match image {
+        DynamicImage::ImageRgb32F(_) => Some(DynamicImage::ImageRgb16(image.to_rgb16())),
+        DynamicImage::ImageRgba32F(_) => Some(DynamicImage::ImageRgba16(image.to_rgba16())),
+        _ => None,
+    };
+    let image = converted.as_ref().unwrap_or(image);
+
+    let mut destination = DynamicImage::new(width, height, image.color());
+    fr::Resizer::new()
+        .resize(image, &mut destination, &fr::ResizeOptions::new().resize_alg(algorithm(filter)))
+        .map_err(|e| format!("Failed to resize image: {}", e))?;
+
+    Ok(destination)
+}
+
+fn algorithm(filter: ResampleFilter) -> fr::ResizeAlg {
+    match filter {
+        ResampleFilter::Nearest => fr::ResizeAlg::Nearest,
+        ResampleFilter::Box => fr::ResizeAlg::Convolution(fr::FilterType::Box),
+        ResampleFilter::Bilinear => fr::ResizeAlg::Convolution(fr::FilterType::Bilinear),
+        ResampleFilter::Hamming => fr::ResizeAlg::Convolution(fr::FilterType::Hamming),
+        ResampleFilter::CatmullRom => fr::ResizeAlg::Convolution(fr::FilterType::CatmullRom),
+        ResampleFilter::Mitchell => fr::ResizeAlg::Convolution(fr::FilterType::Mitchell),
+        ResampleFilter::Lanczos3 => fr::ResizeAlg::Convolution(fr::FilterType::Lanczos3),
+    }
+}
+
+pub struct FastImageResizer;
+
+impl Resizer for FastImageResizer {
+    fn resize(
+        &self,
+        pixels: &[u8],
+        channels: usize,
+        (width, height): (u32, u32),
+        (target_width, target_height): (u32, u32),
+        filter: ResampleFilter,
+    ) -> Result<Box<[u8]>, String> {
+        let pixel_type = match channels {
+            1 => fr::PixelType::U8,
+            2 => fr::PixelType::U8x2,
+            3 => fr::PixelType::U8x3,
+            _ => fr::PixelType::U8x4,
+        };
+
+        let source = fr::images::ImageRef::new(width, height, pixels, pixel_type)
+            .map_err(|e| format!("Invalid source image: {}", e))?;
+        let mut destination = fr::images::Image::new(target_width, target_height, pixel_type);
+
+        // Alpha is premultiplied on an internal copy, the source stays as it is
+        fr::Resizer::new()
+            .resize(&source, &mut destination, &fr::ResizeOptions::new().resize_alg(algorithm(filter)))
+            .map_err(|e| format!("Failed to resize image: {}", e))?;
+
+        Ok(destination.into_vec().into_boxed_slice())
+    }
+}
@@ -0,0 +1,568 @@
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use image::metadata::Orientation;
+use rexiv2::Metadata;
+use serde::{Deserialize, Serialize};
+use tiff::decoder::{ifd::Value, Decoder};
+use tiff::tags::Tag;
+
+/// What images without any stored resolution are assumed to have, as browsers and most editors do.
+pub const DEFAULT_DPI: f64 = 72.0;
+
+const CENTIMETERS_PER_INCH: f64 = 2.54;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResolutionUnit {
+    #[default]
+    Inch,
+    Centimeter,
+}
+
+impl ResolutionUnit {
+    /// How many of this unit make up an inch.
+    pub fn per_inch(self) -> f64 {
+        match self {
+            ResolutionUnit::Inch => 1.0,
+            ResolutionUnit::Centimeter => CENTIMETERS_PER_INCH,
+        }
+    }
+}
+
+/// Where a resolution was read from. Everything but `Default` was measured from the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResolutionSource {
+    /// The `pHYs` chunk of a PNG
+    Png,
+    /// The density in a JPEG's JFIF header
+    Jfif,
+    /// `XResolution`/`YResolution` of a TIFF based file
+    Tiff,
+    /// The EXIF block, for formats without a resolution of their own
+    Exif,
+    /// The density an SVG was rasterized at
+    Svg,
+    /// Set for printing, see [`crate::image::print`]
+    Requested,
+    /// Nothing usable in the file, [`DEFAULT_DPI`] is assumed
+    Default,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Resolution {
+    pub x_dpi: f64,
+    pub y_dpi: f64,
+    /// Unit the file stores its resolution in, the values above are always per inch
+    pub unit: ResolutionUnit,
+    pub source: ResolutionSource,
+    pub measured: bool,
+}
+
+impl Resolution {
+    pub fn new(x_dpi: f64, y_dpi: f64, unit: ResolutionUnit, source: ResolutionSource) -> Self {
+        Self {
+            x_dpi,
+            y_dpi,
+            unit,
+            source,
+            measured: source != ResolutionSource::Default,
+        }
+    }
+
+    pub fn fallback() -> Self {
+        Self::new(DEFAULT_DPI, DEFAULT_DPI, ResolutionUnit::Inch, ResolutionSource::Default)
+    }
+
+    // A value in `unit` per pixel count, `None` when it cannot be a real resolution
+    fn from_density(x: f64, y: f64, unit: ResolutionUnit, source: ResolutionSource) -> Option<Self> {
+        let (x_dpi, y_dpi) = (x * unit.per_inch(), y * unit.per_inch());
+
+        let plausible = |dpi: f64| dpi.is_finite() && (1.0..=100_000.0).contains(&dpi);
+        (plausible(x_dpi) && plausible(y_dpi)).then(|| Self::new(x_dpi, y_dpi, unit, source))
+    }
+
+    /// Single DPI value as reported next to the image, the average when X and Y differ.
+    pub fn dpi(&self) -> u32 {
+        ((self.x_dpi + self.y_dpi) / 2.0).round() as u32
+    }
+
+    /// Both values per `unit` of this resolution, the way they go back into a file.
+    pub fn per_unit(&self) -> (f64, f64) {
+        (self.x_dpi / self.unit.per_inch(), self.y_dpi / self.unit.per_inch())
+    }
+
+    /// Resolution of the pixels after `orientation` is applied, turning by 90° swaps X and Y.
+    pub fn oriented(self, orientation: Orientation) -> Self {
+        match orientation {
+            Orientation::Rotate90 | Orientation::Rotate270 | Orientation::Rotate90FlipH | Orientation::Rotate270FlipH => Self {
+                x_dpi: self.y_dpi,
+                y_dpi: self.x_dpi,
+                ..self
+            },
+            _ => self,
+        }
+    }
+}
+
+fn read_png(reader: &mut (impl Read + Seek)) -> Option<Resolution> {
+    let mut signature = [0; 8];
+    reader.read_exact(&mut signature).ok()?;
+    if signature != PNG_SIGNATURE {
+        return None;
+    }
+
+    // pHYs has to come before the image data, so the search stops there
+    loop {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header).ok()?;
+        let length = u32::from_be_bytes(header[..4].try_into().unwrap());
+
+        match &header[4..] {
+            b"pHYs" => {
+                let mut data = [0; 9];
+                reader.read_exact(&mut data).ok()?;
+
+                let x = u32::from_be_bytes(data[..4].try_into().unwrap()) as f64;
+                let y = u32::from_be_bytes(data[4..8].try_into().unwrap()) as f64;
+
+                // Unit 0 only gives the pixel aspect ratio, 1 is pixels per metre
+                return match data[8] {
+                    1 => Resolution::from_density(x / 100.0, y / 100.0, ResolutionUnit::Centimeter, ResolutionSource::Png),
+                    _ => None,
+                };
+            }
+            b"IDAT" | b"IEND" => return None,
+            _ => {
+                // Chunk data plus its CRC
+                reader.seek(SeekFrom::Current(length as i64 + 4)).ok()?;
+            }
+        }
+    }
+}
+
+fn read_jfif(reader: &mut impl Read) -> Option<Resolution> {
+    // SOI, then the APP0 segment JFIF requires to come first
+    let mut header = [0; 18];
+    reader.read_exact(&mut header).ok()?;
+    if header[..4] != [0xFF, 0xD8, 0xFF, 0xE0] || &header[6..11] != b"JFIF\0" {
+        return None;
+    }
+
+    let units = header[13];
+    let x = u16::from_be_bytes([header[14], header[15]]) as f64;
+    let y = u16::from_be_bytes([header[16], header[17]]) as f64;
+
+    // Units 0 only gives the pixel aspect ratio
+    match units {
+        1 => Resolution::from_density(x, y, ResolutionUnit::Inch, ResolutionSource::Jfif),
+        2 => Resolution::from_density(x, y, ResolutionUnit::Centimeter, ResolutionSource::Jfif),
+        _ => None,
+    }
+}
+
+fn rational(value: Value) -> Option<f64> {
+    match value {
+        Value::Rational(numerator, denominator) if denominator != 0 => Some(numerator as f64 / denominator as f64),
+        Value::Short(value) => Some(value as f64),
+        Value::Unsigned(value) => Some(value as f64),
+        Value::Float(value) => Some(value as f64),
+        Value::Double(value) => Some(value),
+        _ => None,
+    }
+}
+
+fn read_tiff(reader: impl Read + Seek) -> Option<Resolution> {
+    let mut decoder = Decoder::new(reader).ok()?;
+
+    let x = decoder.find_tag(Tag::XResolution).ok()?.and_then(rational)?;
+    let y = decoder.find_tag(Tag::YResolution).ok()?.and_then(rational).unwrap_or(x);
+
+    // Inches when the tag is missing, as the TIFF spec says. 1 means there is no unit at all
+    let unit = match decoder.find_tag_unsigned::<u16>(Tag::ResolutionUnit).ok()? {
+        None | Some(2) => ResolutionUnit::Inch,
+        Some(3) => ResolutionUnit::Centimeter,
+        _ => return None,
+    };
+
+    Resolution::from_density(x, y, unit, ResolutionSource::Tiff)
+}
+
+fn parse_rational(value: &str) -> Option<f64> {
+    match value.split_once('/') {
+        Some((numerator, denominator)) => {
+            let denominator: f64 = denominator.trim().parse().ok()?;
+            (denominator != 0.0).then_some(numerator.trim().parse::<f64>().ok()? / denominator)
+        }
+        None => value.trim().parse().ok(),
+    }
+}
+
+fn read_exif(path: &Path) -> Option<Resolution> {
+    let metadata = Metadata::new_from_path(path).ok()?;
+    if !metadata.has_tag("Exif.Image.XResolution") {
+        return None;
+    }
+
+    let x = parse_rational(&metadata.get_tag_string("Exif.Image.XResolution").ok()?)?;
+    let y = metadata
+        .get_tag_string("Exif.Image.YResolution")
+        .ok()
+        .and_then(|value| parse_rational(&value))
+        .unwrap_or(x);
+
+    let unit = match metadata.has_tag("Exif.Image.ResolutionUnit").then(|| metadata.get_tag_numeric("Exif.Image.ResolutionUnit")) {
+        None | Some(2) => ResolutionUnit::Inch,
+        Some(3) => ResolutionUnit::Centimeter,
+        _ => return None,
+    };
+
+    Resolution::from_density(x, y, unit, ResolutionSource::Exif)
+}
+
+/// Resolution stored in `path`, read from the container's own field where it has one
+/// (PNG `pHYs`, JPEG JFIF density, TIFF tags) and from EXIF otherwise.
+/// Files that store none, or only a pixel aspect ratio, get [`DEFAULT_DPI`].
+pub fn read_resolution(path: &Path) -> Resolution {
+    let native = File::open(path).ok().and_then(|file| {
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).ok()?;
+        reader.rewind().ok()?;
+
+        match magic {
+            [0x89, b'P', b'N', b'G'] => read_png(&mut reader),
+            [0xFF, 0xD8, ..] => read_jfif(&mut reader),
+            [b'I', b'I', 42 | 43, 0] | [b'M', b'M', 0, 42 | 43] => read_tiff(reader),
+            _ => None,
+        }
+    });
+
+    native
+        .or_else(|| read_exif(path))
+        .unwrap_or_else(Resolution::fallback)
+}
+
+// CRC-32 as PNG chunks use it, only a handful of bytes are ever checked so no table is kept
+fn png_crc(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn write_png(bytes: &[u8], resolution: &Resolution) -> Option<Vec<u8>> {
+    // pHYs only knows metres, the unit the file asked for is lost here
+    let per_metre = |dpi: f64| (dpi / CENTIMETERS_PER_INCH * 100.0).round() as u32;
+
+    let mut chunk = b"pHYs".to_vec();
+    chunk.extend(per_metre(resolution.x_dpi).to_be_bytes());
+    chunk.extend(per_metre(resolution.y_dpi).to_be_bytes());
+    chunk.push(1);
+    let crc = png_crc(&chunk);
+
+    let mut output = bytes.get(..PNG_SIGNATURE.len())?.to_vec();
+    let mut position = PNG_SIGNATURE.len();
+
+    // The new chunk goes right after IHDR, an old one is dropped wherever it was
+    while position < bytes.len() {
+        let length = u32::from_be_bytes(bytes.get(position..position + 4)?.try_into().unwrap()) as usize;
+        let end = position + 12 + length;
+        let kind = bytes.get(position + 4..position + 8)?;
+
+        if kind != b"pHYs" {
+            output.extend(bytes.get(position..end)?);
+        }
+        if kind == b"IHDR" {
+            output.extend((chunk.len() as u32 - 4).to_be_bytes());
+            output.extend(&chunk);
+            output.extend(crc.to_be_bytes());
+        }
+
+        position = end;
+    }
+
+    Some(output)
+}
+
+fn write_jfif(bytes: &mut [u8], resolution: &Resolution) -> bool {
+    if bytes.len() < 18 || bytes[..4] != [0xFF, 0xD8, 0xFF, 0xE0] || &bytes[6..11] != b"JFIF\0" {
+        return false;
+    }
+
+    let (x, y) = resolution.per_unit();
+    let (Ok(x), Ok(y)) = (u16::try_from(x.round() as u32), u16::try_from(y.round() as u32)) else {
+        return false;
+    };
+
+    bytes[13] = match resolution.unit {
+        ResolutionUnit::Inch => 1,
+        ResolutionUnit::Centimeter => 2,
+    };
+    bytes[14..16].copy_from_slice(&x.to_be_bytes());
+    bytes[16..18].copy_from_slice(&y.to_be_bytes());
+    true
+}
+
+fn write_exif(path: &Path, resolution: &Resolution) -> Result<(), String> {
+    let metadata = Metadata::new_from_path(path).map_err(|e| format!("Failed to read metadata of {:?}: {}", path, e))?;
+    if !metadata.supports_exif() {
+        return Ok(());
+    }
+
+    let (x, y) = resolution.per_unit();
+    let rational = |value: f64| format!("{}/10000", (value * 10_000.0).round() as u32);
+    let unit = match resolution.unit {
+        ResolutionUnit::Inch => 2,
+        ResolutionUnit::Centimeter => 3,
+    };
+
+    metadata
+        .set_tag_string("Exif.Image.XResolution", &rational(x))
+        .and_then(|_| metadata.set_tag_string("Exif.Image.YResolution", &rational(y)))
+        .and_then(|_| metadata.set_tag_numeric("Exif.Image.ResolutionUnit", unit))
+        .and_then(|_| metadata.save_to_file(path))
+        .map_err(|e| format!("Failed to write resolution to {:?}: {}", path, e))
+}
+
+/// Stores `resolution` in the file at `path`: in the PNG `pHYs` chunk or the JFIF density,
+/// and in EXIF for every format that can hold it, so readers that only look at one of them agree.
+pub fn write_resolution(path: &Path, resolution: &Resolution) -> Result<(), String> {
+    let mut bytes = fs::read(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+
+    let native = if bytes.starts_with(PNG_SIGNATURE) {
+        write_png(&bytes, resolution)
+    } else if write_jfif(&mut bytes, resolution) {
+        Some(bytes)
+    } else {
+        None
+    };
+
+    if let Some(bytes) = native {
+        fs::write(path, bytes).map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+    }
+
+    write_exif(path, resolution)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use tiff::encoder::{colortype, Rational, TiffEncoder};
+    use tiff::tags::ResolutionUnit as TiffResolutionUnit;
+
+    use super::*;
+
+    fn png_chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut body = kind.to_vec();
+        body.extend(data);
+
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend(&body);
+        chunk.extend(png_crc(&body).to_be_bytes());
+        chunk
+    }
+
+    fn phys(x: u32, y: u32, unit: u8) -> Vec<u8> {
+        let mut data = x.to_be_bytes().to_vec();
+        data.extend(y.to_be_bytes());
+        data.push(unit);
+        png_chunk(b"pHYs", &data)
+    }
+
+    // Signature, a 1x1 greyscale IHDR, the given chunks, then the image data
+    fn png(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = PNG_SIGNATURE.to_vec();
+        bytes.extend(png_chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]));
+        bytes.extend(chunks.concat());
+        bytes.extend(png_chunk(b"IDAT", &[0x78, 0x01, 0x63, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01]));
+        bytes.extend(png_chunk(b"IEND", &[]));
+        bytes
+    }
+
+    // Chunk types in file order, failing on any chunk whose CRC is wrong
+    fn chunk_kinds(bytes: &[u8]) -> Vec<String> {
+        let mut kinds = Vec::new();
+        let mut position = PNG_SIGNATURE.len();
+        while position < bytes.len() {
+            let length = u32::from_be_bytes(bytes[position..position + 4].try_into().unwrap()) as usize;
+            let body = &bytes[position + 4..position + 8 + length];
+            let crc = u32::from_be_bytes(bytes[position + 8 + length..position + 12 + length].try_into().unwrap());
+            assert_eq!(crc, png_crc(body), "CRC of {}", String::from_utf8_lossy(&body[..4]));
+
+            kinds.push(String::from_utf8_lossy(&body[..4]).into_owned());
+            position += 12 + length;
+        }
+        kinds
+    }
+
+    fn jfif(units: u8, x: u16, y: u16) -> Vec<u8> {
+        let mut bytes = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10];
+        bytes.extend(b"JFIF\0");
+        bytes.extend([1, 2, units]);
+        bytes.extend(x.to_be_bytes());
+        bytes.extend(y.to_be_bytes());
+        bytes.extend([0, 0, 0xFF, 0xD9]);
+        bytes
+    }
+
+    fn tiff(unit: TiffResolutionUnit, x: Rational, y: Rational) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        {
+            let mut encoder = TiffEncoder::new(&mut bytes).unwrap();
+            let mut image = encoder.new_image::<colortype::Gray8>(1, 1).unwrap();
+            image.resolution_unit(unit);
+            image.x_resolution(x);
+            image.y_resolution(y);
+            image.write_data(&[0]).unwrap();
+        }
+        bytes.into_inner()
+    }
+
+    // Whole pixels per metre are up to 0.013 DPI off
+    fn assert_dpi(resolution: &Resolution, x_dpi: f64, y_dpi: f64) {
+        assert!((resolution.x_dpi - x_dpi).abs() < 0.02, "x: {} != {}", resolution.x_dpi, x_dpi);
+        assert!((resolution.y_dpi - y_dpi).abs() < 0.02, "y: {} != {}", resolution.y_dpi, y_dpi);
+    }
+
+    #[test]
+    fn png_crc_matches_the_spec() {
+        // Every PNG ends with this exact IEND chunk
+        assert_eq!(png_crc(b"IEND"), 0xAE42_6082);
+    }
+
+    #[test]
+    fn png_resolution_is_read_per_metre() {
+        // 11811 pixels per metre is 300 DPI
+        let bytes = png(&[phys(11811, 5906, 1)]);
+        let resolution = read_png(&mut Cursor::new(bytes)).unwrap();
+
+        assert_dpi(&resolution, 300.0, 150.0);
+        assert_eq!(resolution.unit, ResolutionUnit::Centimeter);
+        assert_eq!(resolution.source, ResolutionSource::Png);
+        assert!(resolution.measured);
+    }
+
+    #[test]
+    fn png_without_a_resolution_has_none() {
+        assert!(read_png(&mut Cursor::new(png(&[]))).is_none());
+
+        // An unknown unit only gives the pixel aspect ratio
+        assert!(read_png(&mut Cursor::new(png(&[phys(2, 1, 0)]))).is_none());
+    }
+
+    #[test]
+    fn png_resolution_after_the_image_data_is_ignored() {
+        let mut bytes = png(&[]);
+        let iend = bytes.len() - 12;
+        bytes.splice(iend..iend, phys(11811, 11811, 1));
+
+        assert!(read_png(&mut Cursor::new(bytes)).is_none());
+    }
+
+    #[test]
+    fn png_write_adds_a_chunk_after_the_header() {
+        let resolution = Resolution::new(300.0, 300.0, ResolutionUnit::Inch, ResolutionSource::Requested);
+        let written = write_png(&png(&[]), &resolution).unwrap();
+
+        assert_eq!(chunk_kinds(&written), ["IHDR", "pHYs", "IDAT", "IEND"]);
+        assert_dpi(&read_png(&mut Cursor::new(written)).unwrap(), 300.0, 300.0);
+    }
+
+    #[test]
+    fn png_write_replaces_the_old_chunk() {
+        let original = png(&[png_chunk(b"tEXt", b"Comment\0kept"), phys(2835, 2835, 1)]);
+        let resolution = Resolution::new(600.0, 200.0, ResolutionUnit::Inch, ResolutionSource::Requested);
+        let written = write_png(&original, &resolution).unwrap();
+
+        assert_eq!(chunk_kinds(&written), ["IHDR", "pHYs", "tEXt", "IDAT", "IEND"]);
+        assert_dpi(&read_png(&mut Cursor::new(written)).unwrap(), 600.0, 200.0);
+    }
+
+    #[test]
+    fn png_write_rejects_a_truncated_file() {
+        let mut bytes = png(&[]);
+        bytes.truncate(bytes.len() - 6);
+        let resolution = Resolution::new(300.0, 300.0, ResolutionUnit::Inch, ResolutionSource::Requested);
+
+        assert!(write_png(&bytes, &resolution).is_none());
+    }
+
+    #[test]
+    fn jfif_resolution_is_read_in_its_unit() {
+        let inches = read_jfif(&mut Cursor::new(jfif(1, 300, 150))).unwrap();
+        assert_dpi(&inches, 300.0, 150.0);
+        assert_eq!(inches.unit, ResolutionUnit::Inch);
+        assert_eq!(inches.source, ResolutionSource::Jfif);
+
+        let centimeters = read_jfif(&mut Cursor::new(jfif(2, 100, 100))).unwrap();
+        assert_dpi(&centimeters, 254.0, 254.0);
+        assert_eq!(centimeters.unit, ResolutionUnit::Centimeter);
+
+        // Units 0 is an aspect ratio, the JFIF default of 1:1
+        assert!(read_jfif(&mut Cursor::new(jfif(0, 1, 1))).is_none());
+    }
+
+    #[test]
+    fn jfif_write_stores_the_requested_unit() {
+        let mut bytes = jfif(1, 72, 72);
+        let resolution = Resolution::new(254.0, 127.0, ResolutionUnit::Centimeter, ResolutionSource::Requested);
+        assert!(write_jfif(&mut bytes, &resolution));
+
+        assert_eq!(bytes[13], 2);
+        assert_dpi(&read_jfif(&mut Cursor::new(bytes)).unwrap(), 254.0, 127.0);
+    }
+
+    #[test]
+    fn jfif_write_leaves_other_files_alone() {
+        // An EXIF JPEG starts with APP1 instead
+        let mut bytes = jfif(1, 72, 72);
+        bytes[3] = 0xE1;
+        let original = bytes.clone();
+        let resolution = Resolution::new(300.0, 300.0, ResolutionUnit::Inch, ResolutionSource::Requested);
+
+        assert!(!write_jfif(&mut bytes, &resolution));
+        assert_eq!(bytes, original);
+
+        // More dots per unit than the 16 bit field holds
+        let huge = Resolution::new(70_000.0, 70_000.0, ResolutionUnit::Inch, ResolutionSource::Requested);
+        assert!(!write_jfif(&mut bytes, &huge));
+    }
+
+    #[test]
+    fn tiff_resolution_is_read_from_its_tags() {
+        let bytes = tiff(TiffResolutionUnit::Centimeter, Rational { n: 1181, d: 10 }, Rational { n: 59, d: 1 });
+        let resolution = read_tiff(Cursor::new(bytes)).unwrap();
+
+        assert_dpi(&resolution, 299.974, 149.86);
+        assert_eq!(resolution.unit, ResolutionUnit::Centimeter);
+        assert_eq!(resolution.source, ResolutionSource::Tiff);
+    }
+
+    #[test]
+    fn tiff_resolution_without_a_unit_has_none() {
+        // No unit means the values are only an aspect ratio
+        let bytes = tiff(TiffResolutionUnit::None, Rational { n: 1, d: 1 }, Rational { n: 1, d: 1 });
+        assert!(read_tiff(Cursor::new(bytes)).is_none());
+
+        let bytes = tiff(TiffResolutionUnit::Inch, Rational { n: 300, d: 0 }, Rational { n: 300, d: 0 });
+        assert!(read_tiff(Cursor::new(bytes)).is_none());
+    }
+
+    #[test]
+    fn resolution_turns_with_the_image() {
+        let resolution = Resolution::new(300.0, 150.0, ResolutionUnit::Inch, ResolutionSource::Tiff);
+
+        assert_dpi(&resolution.oriented(Orientation::Rotate90), 150.0, 300.0);
+        assert_dpi(&resolution.oriented(Orientation::Rotate180), 300.0, 150.0);
+    }
+}
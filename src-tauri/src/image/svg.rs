@@ -0,0 +1,154 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use fimg::{DynImage, Image};
+use image::{DynamicImage, RgbaImage};
+use once_cell::sync::Lazy;
+use resvg::tiny_skia::{Pixmap, Transform};
+use resvg::usvg::{fontdb, Options, Tree};
+use serde_json::json;
+use tauri::State;
+
+use crate::global::IMAGE_CACHE_DIR;
+use crate::image::cache;
+use crate::image::cache_index::CacheIndex;
+use crate::image::error::ImportError;
+use crate::utilities::file_utils;
+
+/// One SVG user unit is one CSS pixel, which is defined at 96 DPI.
+pub const SVG_BASE_DPI: f32 = 96.0;
+
+// Keeps a document with a huge viewBox from allocating gigabytes of pixels
+const MAXIMUM_RASTER_DIMENSION: u32 = 16384;
+
+// Loading the system fonts takes a while, share them between every document
+static FONTS: Lazy<Arc<fontdb::Database>> = Lazy::new(|| {
+    let mut fonts = fontdb::Database::new();
+    fonts.load_system_fonts();
+    Arc::new(fonts)
+});
+
+pub const SVG_EXTENSIONS: [&str; 2] = ["svg", "svgz"];
+
+pub fn is_svg(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| SVG_EXTENSIONS.iter().any(|known| extension.eq_ignore_ascii_case(known)))
+}
+
+/// Parses an SVG or gzipped SVGZ document, resolving relative references from its own directory.
+pub fn load_svg(path: &Path) -> Result<Tree, ImportError> {
+    let data = std::fs::read(path).map_err(ImportError::Open)?;
+
+    let mut options = Options {
+        resources_dir: path.parent().map(Path::to_path_buf),
+        ..Options::default()
+    };
+    options.fontdb = FONTS.clone();
+
+    Tree::from_data(&data, &options).map_err(|e| ImportError::Rasterize(e.to_string()))
+}
+
+/// Pixel size to render `tree` at: its document size at `dpi`, or scaled so the longest
+/// edge is `target_size` when one is given.
+pub fn raster_dimensions(tree: &Tree, dpi: f32, target_size: Option<u32>) -> (u32, u32) {
+    let size = tree.size();
+    let longest_edge = size.width().max(size.height());
+
+    let scale = match target_size {
+        Some(target_size) => target_size as f32 / longest_edge,
+        None => dpi / SVG_BASE_DPI,
+    };
+    let scale = scale.min(MAXIMUM_RASTER_DIMENSION as f32 / longest_edge);
+
+    let width = (size.width() * scale).round().max(1.0) as u32;
+    let height = (size.height() * scale).round().max(1.0) as u32;
+
+    (width, height)
+}
+
+pub fn rasterize(tree: &Tree, width: u32, height: u32) -> Result<DynamicImage, ImportError> {
+    let mut pixmap = Pixmap::new(width, height)
+        .ok_or_else(|| ImportError::Rasterize(format!("Cannot render at {}x{}", width, height)))?;
+
+    let size = tree.size();
+    let transform = Transform::from_scale(width as f32 / size.width(), height as f32 / size.height());
+    resvg::render(tree, transform, &mut pixmap.as_mut());
+
+    // tiny-skia renders premultiplied alpha, the rest of the pipeline expects straight alpha
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+
+    let buffer = RgbaImage::from_raw(width, height, pixels)
+        .ok_or_else(|| ImportError::Rasterize("Rendered pixels do not match the image size".to_string()))?;
+
+    Ok(DynamicImage::ImageRgba8(buffer))
+}
+
+/// Copies the source document next to the high-res raster so it can be rendered again later.
+pub fn keep_vector(source: &Path, highres_dir: &Path, hash: &str) -> Result<PathBuf, ImportError> {
+    let extension = source
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("svg")
+        .to_lowercase();
+    let destination = highres_dir.join(format!("{}.{}", hash, extension));
+
+    if !destination.exists() {
+        file_utils::write_atomically(&destination, |path| std::fs::copy(source, path).map(|_| ())).map_err(ImportError::Save)?;
+    }
+
+    Ok(destination)
+}
+
+fn render_cached_vector(vector_path: &Path, raster_path: &Path, longest_edge: u32) -> Result<serde_json::Value, String> {
+    let tree = load_svg(vector_path).map_err(|e| e.to_string())?;
+    let (width, height) = raster_dimensions(&tree, SVG_BASE_DPI, Some(longest_edge));
+
+    if !raster_path.exists() {
+        let raster = rasterize(&tree, width, height).map_err(|e| e.to_string())?;
+        let raster = DynImage::Rgba(Image::build(width, height).buf(raster.into_bytes()));
+
+        file_utils::write_atomically(raster_path, |path| file_utils::write_dyn_png(&raster, path))
+            .map_err(|e| format!("Failed to save raster: {}", e))?;
+    }
+
+    Ok(json!({
+        "path": raster_path.to_str().unwrap(),
+        "width": width,
+        "height": height
+    }))
+}
+
+/// Renders the kept vector document of an imported SVG again, with its longest edge at
+/// `longest_edge` pixels. Renders are cached as `highres/<hash>.<longest_edge>.png`.
+#[tauri::command]
+pub async fn rasterize_svg(
+    hash: String,
+    longest_edge: u32,
+    index: State<'_, CacheIndex>,
+) -> Result<serde_json::Value, String> {
+    cache::validate_hash(&hash)?;
+    let entry = index.entry(&hash)?.ok_or_else(|| format!("{} is not in the cache", hash))?;
+    let vector_path = entry["vector"]["path"]
+        .as_str()
+        .map(PathBuf::from)
+        .ok_or_else(|| format!("{} was not imported from an SVG", hash))?;
+
+    let longest_edge = longest_edge.clamp(1, MAXIMUM_RASTER_DIMENSION);
+    let raster_path = IMAGE_CACHE_DIR
+        .lock()
+        .unwrap()
+        .join("highres")
+        .join(format!("{}.{}.png", hash, longest_edge));
+
+    tokio::task::spawn_blocking(move || render_cached_vector(&vector_path, &raster_path, longest_edge))
+        .await
+        .map_err(|e| format!("Rasterize task failed: {}", e))?
+}
@@ -0,0 +1,106 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufWriter, Seek, Write};
+use std::path::Path;
+
+use image::DynamicImage;
+use tiff::encoder::colortype::{self, ColorType};
+use tiff::encoder::{Rational, TiffEncoder, TiffValue};
+use tiff::tags::{ResolutionUnit as TiffResolutionUnit, Tag, Type};
+use tiff::TiffResult;
+
+use crate::image::icc::{IccProfile, ICC_PROFILE_TAG};
+use crate::image::resolution::{Resolution, ResolutionUnit};
+
+// Four decimals are plenty for a resolution and keep 100000 DPI inside a u32
+fn to_rational(value: f64) -> Rational {
+    Rational { n: (value * 10_000.0).round() as u32, d: 10_000 }
+}
+
+// The spec stores ICC profiles as UNDEFINED, readers that find BYTE there skip the profile
+struct Undefined<'a>(&'a [u8]);
+
+impl TiffValue for Undefined<'_> {
+    const BYTE_LEN: u8 = 1;
+    const FIELD_TYPE: Type = Type::UNDEFINED;
+
+    fn count(&self) -> usize {
+        self.0.len()
+    }
+
+    fn data(&self) -> Cow<[u8]> {
+        Cow::Borrowed(self.0)
+    }
+}
+
+fn write_image<C, W>(
+    encoder: &mut TiffEncoder<W>,
+    width: u32,
+    height: u32,
+    data: &[C::Inner],
+    resolution: Option<&Resolution>,
+    icc_profile: Option<&IccProfile>,
+) -> TiffResult<()>
+where
+    C: ColorType,
+    [C::Inner]: TiffValue,
+    W: Write + Seek,
+{
+    let mut image = encoder.new_image::<C>(width, height)?;
+
+    if let Some(resolution) = resolution {
+        let (x, y) = resolution.per_unit();
+        image.resolution_unit(match resolution.unit {
+            ResolutionUnit::Inch => TiffResolutionUnit::Inch,
+            ResolutionUnit::Centimeter => TiffResolutionUnit::Centimeter,
+        });
+        image.x_resolution(to_rational(x));
+        image.y_resolution(to_rational(y));
+    }
+
+    if let Some(icc_profile) = icc_profile {
+        image.encoder().write_tag(Tag::Unknown(ICC_PROFILE_TAG), Undefined(&icc_profile.data))?;
+    }
+
+    image.write_data(data)
+}
+
+/// Writes `image` as an uncompressed TIFF without losing bit depth or alpha.
+/// TIFF has no grey + alpha colour type in the encoder, so those are widened to RGBA
+/// of the same depth, which keeps every sample value. A measured `resolution` is stored
+/// in the resolution tags, a defaulted one is left out so it is not mistaken for a real one.
+/// The source's ICC profile is kept when it fits the written samples, the pixels are not converted.
+pub fn write_tiff(
+    image: &DynamicImage,
+    output_path: &Path,
+    resolution: Option<&Resolution>,
+    icc_profile: Option<&IccProfile>,
+) -> TiffResult<()> {
+    let file = File::create(output_path)?;
+    let mut encoder = TiffEncoder::new(BufWriter::new(file))?;
+
+    let (width, height) = (image.width(), image.height());
+    let resolution = resolution.filter(|resolution| resolution.measured);
+
+    // Grey + alpha is written as RGBA and CMYK sources are decoded to RGB, a profile for
+    // another colour space than the written samples would make readers misread them
+    let written_space = match image {
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageLuma16(_) => "gray",
+        _ => "rgb",
+    };
+    let icc_profile = icc_profile.filter(|icc_profile| icc_profile.color_space == Some(written_space));
+
+    match image {
+        DynamicImage::ImageLuma8(buffer) => write_image::<colortype::Gray8, _>(&mut encoder, width, height, buffer.as_raw(), resolution, icc_profile),
+        DynamicImage::ImageLuma16(buffer) => write_image::<colortype::Gray16, _>(&mut encoder, width, height, buffer.as_raw(), resolution, icc_profile),
+        DynamicImage::ImageLumaA8(_) => write_image::<colortype::RGBA8, _>(&mut encoder, width, height, image.to_rgba8().as_raw(), resolution, icc_profile),
+        DynamicImage::ImageLumaA16(_) => write_image::<colortype::RGBA16, _>(&mut encoder, width, height, image.to_rgba16().as_raw(), resolution, icc_profile),
+        DynamicImage::ImageRgb8(buffer) => write_image::<colortype::RGB8, _>(&mut encoder, width, height, buffer.as_raw(), resolution, icc_profile),
+        DynamicImage::ImageRgb16(buffer) => write_image::<colortype::RGB16, _>(&mut encoder, width, height, buffer.as_raw(), resolution, icc_profile),
+        DynamicImage::ImageRgba8(buffer) => write_image::<colortype::RGBA8, _>(&mut encoder, width, height, buffer.as_raw(), resolution, icc_profile),
+        DynamicImage::ImageRgba16(buffer) => write_image::<colortype::RGBA16, _>(&mut encoder, width, height, buffer.as_raw(), resolution, icc_profile),
+        DynamicImage::ImageRgb32F(buffer) => write_image::<colortype::RGB32Float, _>(&mut encoder, width, height, buffer.as_raw(), resolution, icc_profile),
+        DynamicImage::ImageRgba32F(buffer) => write_image::<colortype::RGBA32Float, _>(&mut encoder, width, height, buffer.as_raw(), resolution, icc_profile),
+        _ => write_image::<colortype::RGBA32Float, _>(&mut encoder, width, height, image.to_rgba32f().as_raw(), resolution, icc_profile),
+    }
+}
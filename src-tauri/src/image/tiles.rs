@@ -0,0 +1,264 @@
+use std::path::{Path, PathBuf};
+
+use fimg::{DynImage, Image};
+use serde_json::json;
+use tauri::State;
+
+use crate::global::IMAGE_CACHE_DIR;
+use crate::image::cache;
+use crate::image::cache_index::CacheIndex;
+use crate::image::jobs::CancellationToken;
+use crate::image::lowres_rs::to_preview_image;
+use crate::image::pages;
+use crate::image::resample::ResampleFilter;
+use crate::image::resize::{self, ResizeBackend};
+use crate::utilities::file_utils;
+
+pub const TILE_SIZE: u32 = 256;
+/// Range tile sizes asked for by the frontend are clamped to.
+pub const MIN_TILE_SIZE: u32 = 64;
+pub const MAX_TILE_SIZE: u32 = 4096;
+const MANIFEST_NAME: &str = "manifest.json";
+
+// Deep Zoom style pyramid: level 0 is a 1x1 image, the last level is the full image
+fn level_count(width: u32, height: u32) -> u32 {
+    let longest_edge = width.max(height).max(1);
+    32 - (longest_edge - 1).leading_zeros() + 1
+}
+
+fn level_dimensions(width: u32, height: u32, level: u32, max_level: u32) -> (u32, u32) {
+    let shift = max_level - level;
+    let level_width = (width as u64).div_ceil(1 << shift) as u32;
+    let level_height = (height as u64).div_ceil(1 << shift) as u32;
+
+    (level_width.max(1), level_height.max(1))
+}
+
+fn crop_tile<T: AsRef<[u8]>>(image: &DynImage<T>, x: u32, y: u32, width: u32, height: u32) -> DynImage<Vec<u8>> {
+    let bytes = image.bytes();
+    let channels = bytes.len() / (image.width() as usize * image.height() as usize);
+    let row_stride = image.width() as usize * channels;
+    let mut buffer = Vec::with_capacity(width as usize * height as usize * channels);
+
+    for row in y..y + height {
+        let start = row as usize * row_stride + x as usize * channels;
+        buffer.extend_from_slice(&bytes[start..start + width as usize * channels]);
+    }
+
+    match image {
+        DynImage::Y(_) => DynImage::Y(Image::build(width, height).buf(buffer)),
+        DynImage::Ya(_) => DynImage::Ya(Image::build(width, height).buf(buffer)),
+        DynImage::Rgb(_) => DynImage::Rgb(Image::build(width, height).buf(buffer)),
+        DynImage::Rgba(_) => DynImage::Rgba(Image::build(width, height).buf(buffer)),
+    }
+}
+
+pub fn get_tiles_dir(hash: &str) -> PathBuf {
+    IMAGE_CACHE_DIR.lock().unwrap().join("tiles").join(hash)
+}
+
+// Later pages of a document or animation have their pyramid inside the one of the first page
+fn get_page_tiles_dir(hash: &str, page: usize) -> PathBuf {
+    match page {
+        0 => get_tiles_dir(hash),
+        page => get_tiles_dir(hash).join(pages::page_name(page)),
+    }
+}
+
+/// Tiles every level into `build_dir` and returns the level list of the manifest.
+fn write_levels(
+    image: &DynImage<Vec<u8>>,
+    build_dir: &Path,
+    tile_size: u32,
+    backend: ResizeBackend,
+    cancel: &CancellationToken,
+) -> Result<Vec<serde_json::Value>, String> {
+    let width = image.width();
+    let height = image.height();
+    let levels = level_count(width, height);
+    let max_level = levels - 1;
+
+    // Walk down from the full resolution image, halving each time, so every level
+    // is scaled from the one above it instead of from the full image
+    let mut current: Option<DynImage<Box<[u8]>>> = None;
+    let mut level_info = Vec::with_capacity(levels as usize);
+
+    for level in (0..levels).rev() {
+        let (level_width, level_height) = level_dimensions(width, height, level, max_level);
+        if level != max_level {
+            current = Some(match &current {
+                Some(previous) => resize::resize(previous, level_width, level_height, ResampleFilter::Lanczos3, backend)?,
+                None => resize::resize(image, level_width, level_height, ResampleFilter::Lanczos3, backend)?,
+            });
+        }
+
+        let level_dir = build_dir.join(level.to_string());
+        file_utils::create_dir_if_not_exists(&level_dir);
+
+        let columns = level_width.div_ceil(tile_size);
+        let rows = level_height.div_ceil(tile_size);
+
+        for row in 0..rows {
+            if cancel.is_cancelled() {
+                return Err("Tile generation cancelled".to_string());
+            }
+
+            for column in 0..columns {
+                let x = column * tile_size;
+                let y = row * tile_size;
+                let tile_width = tile_size.min(level_width - x);
+                let tile_height = tile_size.min(level_height - y);
+
+                let tile = match &current {
+                    Some(scaled) => crop_tile(scaled, x, y, tile_width, tile_height),
+                    None => crop_tile(image, x, y, tile_width, tile_height),
+                };
+                file_utils::write_dyn_png(&tile, &level_dir.join(format!("{}_{}.png", column, row)))
+                    .map_err(|e| format!("Failed to write tile: {}", e))?;
+            }
+        }
+
+        level_info.push(json!({
+            "level": level,
+            "width": level_width,
+            "height": level_height,
+            "columns": columns,
+            "rows": rows
+        }));
+    }
+
+    level_info.reverse();
+
+    Ok(level_info)
+}
+
+/// Generates the pyramid of `image` in `tiles_dir`, or reads the one already there if it was
+/// cut at `tile_size`; one cut at another size is removed and generated again. The levels are
+/// tiled next to it under a temporary name and moved in before the manifest is written, so a
+/// manifest is only ever found next to a complete pyramid.
+pub fn generate_tile_pyramid(
+    image: &DynImage<Vec<u8>>,
+    tiles_dir: &Path,
+    tile_size: u32,
+    backend: ResizeBackend,
+    cancel: &CancellationToken,
+) -> Result<serde_json::Value, String> {
+    if let Some(manifest) = cached_manifest(tiles_dir, tile_size) {
+        return Ok(manifest);
+    }
+
+    // Moving the new levels in keeps files that are already there, so old tiles would stay
+    if tiles_dir.exists() {
+        std::fs::remove_dir_all(tiles_dir).map_err(|e| format!("Failed to remove outdated tiles: {}", e))?;
+    }
+    let manifest_path = tiles_dir.join(MANIFEST_NAME);
+
+    let build_dir = file_utils::temp_path(tiles_dir);
+    let level_info = match write_levels(image, &build_dir, tile_size, backend, cancel) {
+        Ok(level_info) => level_info,
+        Err(e) => {
+            // Only this call's own levels, other workers may be tiling the same image
+            let _ = std::fs::remove_dir_all(&build_dir);
+            return Err(e);
+        }
+    };
+
+    if let Some(parent) = tiles_dir.parent() {
+        file_utils::create_dir_if_not_exists(parent);
+    }
+    file_utils::move_into_place(&build_dir, tiles_dir).map_err(|e| format!("Failed to move tiles into place: {}", e))?;
+
+    let manifest = json!({
+        "width": image.width(),
+        "height": image.height(),
+        "tile_size": tile_size,
+        "format": "png",
+        "max_level": level_count(image.width(), image.height()) - 1,
+        "path": tiles_dir.to_str().unwrap(),
+        "levels": level_info
+    });
+    write_manifest(&manifest_path, &manifest)?;
+
+    Ok(manifest)
+}
+
+fn write_manifest(manifest_path: &Path, manifest: &serde_json::Value) -> Result<(), String> {
+    file_utils::write_atomically(manifest_path, |path| std::fs::write(path, manifest.to_string()))
+        .map_err(|e| format!("Failed to write tile manifest: {}", e))
+}
+
+/// Points the manifest of a pyramid that was moved to `tiles_dir` at its new place.
+pub fn relocate_manifest(tiles_dir: &Path) -> Result<(), String> {
+    let manifest_path = tiles_dir.join(MANIFEST_NAME);
+    let mut manifest = read_manifest(&manifest_path)?;
+    if manifest["path"].as_str() == tiles_dir.to_str() {
+        return Ok(());
+    }
+
+    manifest["path"] = json!(tiles_dir.to_str().unwrap());
+    write_manifest(&manifest_path, &manifest)
+}
+
+/// Manifest of an already generated pyramid cut at `tile_size`, `None` if there is none yet.
+pub fn cached_manifest(tiles_dir: &Path, tile_size: u32) -> Option<serde_json::Value> {
+    read_manifest(&tiles_dir.join(MANIFEST_NAME)).ok()
+        .filter(|manifest| manifest["tile_size"].as_u64() == Some(tile_size as u64))
+}
+
+fn read_manifest(manifest_path: &Path) -> Result<serde_json::Value, String> {
+    let contents = std::fs::read_to_string(manifest_path)
+        .map_err(|e| format!("Failed to read tile manifest: {}", e))?;
+
+    serde_json::from_str(&contents).map_err(|e| format!("Failed to parse tile manifest: {}", e))
+}
+
+fn open_highres_image(hash: &str, page: usize) -> Result<DynImage<Vec<u8>>, String> {
+    Ok(to_preview_image(&cache::open_highres_page(hash, page)?))
+}
+
+#[tauri::command]
+pub async fn get_tile_pyramid(hash: String, page: Option<usize>, index: State<'_, CacheIndex>) -> Result<serde_json::Value, String> {
+    cache::validate_hash(&hash)?;
+    index.record_access(&hash);
+    let page = page.unwrap_or_default();
+    let tiles_dir = get_page_tiles_dir(&hash, page);
+    let manifest_path = tiles_dir.join(MANIFEST_NAME);
+    if manifest_path.exists() {
+        return read_manifest(&manifest_path);
+    }
+
+    // Images imported before tiling existed only have their high-res copy
+    let index = index.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let image = open_highres_image(&hash, page)?;
+        let manifest = generate_tile_pyramid(&image, &tiles_dir, TILE_SIZE, ResizeBackend::default(), &CancellationToken::default())?;
+
+        // The new tiles count towards the image's size from now on
+        index.forget_sizes(&hash)?;
+        Ok(manifest)
+    })
+    .await
+    .map_err(|e| format!("Tile generation task failed: {}", e))?
+}
+
+#[tauri::command]
+pub fn get_tile_path(
+    hash: String,
+    level: u32,
+    column: u32,
+    row: u32,
+    page: Option<usize>,
+    index: State<'_, CacheIndex>,
+) -> Result<String, String> {
+    cache::validate_hash(&hash)?;
+    index.record_access(&hash);
+    let tile_path = get_page_tiles_dir(&hash, page.unwrap_or_default())
+        .join(level.to_string())
+        .join(format!("{}_{}.png", column, row));
+
+    if !tile_path.exists() {
+        return Err(format!("Tile {}/{}_{} does not exist for {}", level, column, row, hash));
+    }
+
+    Ok(tile_path.to_str().unwrap().to_string())
+}
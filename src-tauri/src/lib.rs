@@ -10,8 +10,30 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_opener::init())
+        .manage(image::jobs::JobRegistry::default())
+        .manage(image::cache::CachePins::default())
         .invoke_handler(tauri::generate_handler![
             crate::image::lowres_rs::load_and_resize_images,
+            crate::image::lowres_rs::import_images,
+            crate::image::benchmark::benchmark_pipeline,
+            crate::image::cache::get_cache_stats,
+            crate::image::cache::clear_cache,
+            crate::image::cache::set_cache_limit,
+            crate::image::cache::pin_images,
+            crate::image::cache::unpin_images,
+            crate::image::cache_index::list_cached_images,
+            crate::image::export::export_image,
+            crate::image::jobs::cancel_job,
+            crate::image::jobs::list_jobs,
+            crate::image::metadata::get_metadata,
+            crate::image::metadata_edit::edit_metadata,
+            crate::image::metadata_policy::apply_metadata_policy,
+            crate::image::print::set_print_size,
+            crate::image::python::import_with_python,
+            crate::image::python::filter_with_python,
+            crate::image::svg::rasterize_svg,
+            crate::image::tiles::get_tile_pyramid,
+            crate::image::tiles::get_tile_path,
         ])
         .setup(|app| {
             // Initialize the cache directory once the app is running
@@ -25,6 +47,9 @@ pub fn run() {
 
             *global::IMAGE_CACHE_DIR.lock().unwrap() = image_cache_dir;
 
+            let cache_index = image::cache_index::CacheIndex::open(&app_config_dir.join("cache_index.redb"))?;
+            app.manage(cache_index);
+
             Ok(())
         })
         .run(tauri::generate_context!())
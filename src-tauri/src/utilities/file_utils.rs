@@ -1,6 +1,10 @@
 use std::fs;
+use std::io::BufWriter;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use fimg::{DynImage, WritePng};
 
 use tauri::AppHandle;
 use tauri_plugin_dialog::DialogExt;
@@ -9,14 +13,36 @@ use tauri_plugin_dialog::FilePath;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 
+use crate::image::formats::{HEIF_EXTENSIONS, JXL_EXTENSIONS};
+use crate::image::pages::PAGED_EXTENSIONS;
+use crate::image::raw::RAW_EXTENSIONS;
+use crate::image::svg::SVG_EXTENSIONS;
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// Single page formats the image crate decodes, every other format has its own list next to
+// the code that decodes it
+const SINGLE_PAGE_EXTENSIONS: [&str; 3] = ["jpeg", "jpg", "bmp"];
+
+/// Every extension an import accepts, RAW files included.
+pub fn image_extensions() -> Vec<&'static str> {
+    [
+        &SINGLE_PAGE_EXTENSIONS[..],
+        &PAGED_EXTENSIONS[..],
+        &SVG_EXTENSIONS[..],
+        &HEIF_EXTENSIONS[..],
+        &JXL_EXTENSIONS[..],
+        &RAW_EXTENSIONS[..],
+    ]
+    .concat()
+}
+
 pub fn open_image_dialog(app_handle: AppHandle) -> Vec<String> {
     let file_paths: Option<Vec<FilePath>> = app_handle
         .dialog()
         .file()
-        .add_filter(
-            "Image Files",
-            &["png", "jpeg", "jpg", "gif", "webp", "bmp", "tiff", "svg"],
-        )
+        .add_filter("Image Files", &image_extensions())
+        .add_filter("Camera RAW", &RAW_EXTENSIONS)
         .blocking_pick_files();
 
     file_paths
@@ -31,6 +57,68 @@ pub fn create_dir_if_not_exists(path: &Path) {
     }
 }
 
+/// `.tmp-<pid>-<n>`, unique to this process and call. Cached copies are written under it
+/// next to where they belong and renamed into place once complete.
+pub fn temp_suffix() -> String {
+    format!(".tmp-{}-{}", std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// `path` with a [`temp_suffix`] appended.
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(temp_suffix());
+    PathBuf::from(name)
+}
+
+/// Has `write` write the file under a [`temp_path`] and renames it to `path` once complete, so
+/// nobody checking whether `path` exists ever finds it half written.
+pub fn write_atomically(path: &Path, write: impl FnOnce(&Path) -> std::io::Result<()>) -> std::io::Result<()> {
+    let temp = temp_path(path);
+
+    if let Err(e) = write(&temp) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+
+    move_into_place(&temp, path)
+}
+
+/// Renames the finished `temp` file or directory to `destination`. When another worker got
+/// there first its copy is kept and `temp` removed, directories on both sides are merged.
+pub fn move_into_place(temp: &Path, destination: &Path) -> std::io::Result<()> {
+    if temp.is_dir() && destination.is_dir() {
+        for entry in fs::read_dir(temp)? {
+            let entry = entry?;
+            move_into_place(&entry.path(), &destination.join(entry.file_name()))?;
+        }
+        return fs::remove_dir(temp);
+    }
+
+    if destination.exists() {
+        return if temp.is_dir() { fs::remove_dir_all(temp) } else { fs::remove_file(temp) };
+    }
+
+    match fs::rename(temp, destination) {
+        // Created between the check and the rename
+        Err(_) if destination.exists() => move_into_place(temp, destination),
+        result => result,
+    }
+}
+
+pub fn write_png(image: &impl WritePng, output_path: &Path) -> std::io::Result<()> {
+    let file = fs::File::create(output_path)?;
+    image.write(&mut BufWriter::new(file))
+}
+
+pub fn write_dyn_png<T: AsRef<[u8]>>(image: &DynImage<T>, output_path: &Path) -> std::io::Result<()> {
+    match image {
+        DynImage::Y(image) => write_png(image, output_path),
+        DynImage::Ya(image) => write_png(image, output_path),
+        DynImage::Rgb(image) => write_png(image, output_path),
+        DynImage::Rgba(image) => write_png(image, output_path),
+    }
+}
+
 pub async fn save_file(buffer: Vec<u8>, output_path: &PathBuf) -> Result<(), String> {
     // Create or open the file where you want to save the image asynchronously
     let mut file = File::create(output_path)